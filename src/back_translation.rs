@@ -0,0 +1,63 @@
+use quick_search_lib::SearchResult;
+
+use crate::{
+    backend::{TranslateRequest, TranslateResponse},
+    error::DeepLError,
+    languages, DeepL, SourceLanguageCode, TargetLanguageCode,
+};
+
+// quick_search_lib only lets a result be executed, there's no second action to ask for a back-translation with,
+// so back-translations are shown right below the translation they belong to instead, see "Show back-translations" and the back flag
+
+impl DeepL {
+    // the translation sent back into the language the query was in, deepl tells us which one that was even if the query didn't say
+    pub(crate) fn back_request(&self, request: &TranslateRequest, response: &TranslateResponse) -> Option<TranslateRequest> {
+        let translation = response.translations.first()?;
+        let source = request.source_lang.clone().unwrap_or_else(|| translation.detected_source_language.clone());
+
+        Some(TranslateRequest {
+            text: vec![translation.text.clone()],
+            target_lang: self.back_target(&source),
+            // variants are target only, EN-US text is EN text
+            source_lang: Some(SourceLanguageCode::from_code(request.target_lang.code().split('-').next().unwrap_or_default())),
+            formality: None,
+            // glossaries only work one way round
+            glossary_id: None,
+            split_sentences: request.split_sentences,
+            preserve_formatting: request.preserve_formatting,
+            tag_handling: request.tag_handling,
+            context: None,
+        })
+    }
+
+    // the user's own variant of the language if it's one of their languages, e.g. EN-GB rather than EN for someone with EN-GB as their primary language,
+    // otherwise a variant deepl still takes as a target, the bare EN of a source language isn't one
+    fn back_target(&self, source: &SourceLanguageCode) -> TargetLanguageCode {
+        let my_languages = self.my_languages().ok().flatten();
+        let own = my_languages.and_then(|(primary, secondary)| [primary, secondary].into_iter().find(|language| language.is_language_of(source)));
+
+        own.unwrap_or_else(|| TargetLanguageCode::from_code(&languages::concrete_target(&source.code())))
+    }
+
+    pub(crate) fn back_translation_results(&self, request: &TranslateRequest, response: Result<(TranslateResponse, bool), DeepLError>) -> Vec<SearchResult> {
+        // the translation itself is fine, so a failed back-translation is only logged
+        let (response, cached) = match response {
+            Ok(response) => response,
+            Err(e) => {
+                self.log_error(&e);
+                return vec![];
+            }
+        };
+
+        let mut context = format!("Back-translation into {}", request.target_lang);
+        if cached {
+            context.push_str(" (cached)");
+        }
+
+        response
+            .translations
+            .into_iter()
+            .map(|translation| SearchResult::new(&translation.text).set_context(&context).set_extra_info(&translation.text))
+            .collect()
+    }
+}
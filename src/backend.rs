@@ -0,0 +1,620 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config_number,
+    error::DeepLError,
+    glossary::{CreateGlossaryRequest, GlossaryInfo, ListGlossariesResponse},
+    SourceLanguageCode, TargetLanguageCode, DEFAULT_CONNECT_TIMEOUT_MS, DEFAULT_MAX_RETRIES, DEFAULT_REQUEST_TIMEOUT_MS,
+};
+
+// the launcher waits on every retry, so keep the backoff short
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+// everything the plugin needs from a translation service
+// the DeepL http api is the real implementation, but anything that can answer these (like an in-process fake) can be plugged in instead
+pub trait TranslationBackend: Send + Sync {
+    fn translate(&self, request: &TranslateRequest) -> Result<TranslateResponse, DeepLError>;
+
+    fn languages(&self, language_type: LanguageType) -> Result<Vec<Language>, DeepLError>;
+
+    fn usage(&self) -> Result<UsageResponse, DeepLError>;
+
+    fn list_glossaries(&self) -> Result<Vec<GlossaryInfo>, DeepLError>;
+
+    fn create_glossary(&self, request: &CreateGlossaryRequest) -> Result<GlossaryInfo, DeepLError>;
+
+    fn delete_glossary(&self, glossary_id: &str) -> Result<(), DeepLError>;
+
+    // called whenever the plugin config is (re)loaded, backends that don't care about the config can ignore it
+    fn reload_config(&self, _config: &quick_search_lib::Config) {}
+}
+
+pub struct DeepLBackend {
+    // swapped out as a whole when the config is reloaded, requests that are already running keep the settings they started with
+    settings: RwLock<Arc<Settings>>,
+}
+
+struct Settings {
+    client: reqwest::blocking::Client,
+    api_key: String,
+    use_free_tier: bool,
+    // overrides the free/pro endpoints when set, e.g. for a self-hosted proxy or a local mock server
+    custom_base_url: String,
+    max_retries: u32,
+}
+
+impl Settings {
+    fn from_config(config: &quick_search_lib::Config) -> Self {
+        let connect_timeout = Duration::from_millis(config_number(config, "Connect timeout (ms)", DEFAULT_CONNECT_TIMEOUT_MS).unwrap_or(DEFAULT_CONNECT_TIMEOUT_MS));
+        let request_timeout = Duration::from_millis(config_number(config, "Request timeout (ms)", DEFAULT_REQUEST_TIMEOUT_MS).unwrap_or(DEFAULT_REQUEST_TIMEOUT_MS));
+
+        Self {
+            client: reqwest::blocking::Client::builder()
+                .connect_timeout(connect_timeout)
+                .timeout(request_timeout)
+                .build()
+                .unwrap_or_else(|_| reqwest::blocking::Client::new()),
+            api_key: config.get("DeepL Api Key").and_then(|entry| entry.as_string()).unwrap_or_default().trim().to_owned(),
+            use_free_tier: config.get("Use free tier").and_then(|entry| entry.as_bool()).unwrap_or(true),
+            // accept the base url with or without a trailing slash, the paths we append always start with one
+            custom_base_url: config
+                .get("Custom API base URL")
+                .and_then(|entry| entry.as_string())
+                .unwrap_or_default()
+                .trim()
+                .trim_end_matches('/')
+                .to_owned(),
+            max_retries: config_number(config, "Max retries", DEFAULT_MAX_RETRIES).unwrap_or(DEFAULT_MAX_RETRIES) as u32,
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        let base = if !self.custom_base_url.is_empty() {
+            self.custom_base_url.as_str()
+        } else if self.use_free_tier {
+            "https://api-free.deepl.com"
+        } else {
+            "https://api.deepl.com"
+        };
+        format!("{}{}", base, path)
+    }
+
+    fn auth_header(&self) -> Result<String, DeepLError> {
+        if self.api_key.is_empty() {
+            return Err(DeepLError::NoApiKey);
+        }
+        Ok(format!("DeepL-Auth-Key {}", self.api_key))
+    }
+
+    fn send_json<T: serde::de::DeserializeOwned>(&self, request: reqwest::blocking::RequestBuilder) -> Result<T, DeepLError> {
+        match self.send(request)?.json::<T>() {
+            Ok(response) => Ok(response),
+            Err(e) => Err(DeepLError::Parse(e.to_string())),
+        }
+    }
+
+    fn send(&self, request: reqwest::blocking::RequestBuilder) -> Result<reqwest::blocking::Response, DeepLError> {
+        let request = request.header("Authorization", self.auth_header()?);
+
+        let mut attempt = 0;
+        loop {
+            // every attempt needs its own copy of the request, if it can't be copied it can only be sent once
+            let this_attempt = match request.try_clone() {
+                Some(this_attempt) => this_attempt,
+                None => return self.send_once(request).map_err(|(e, _)| e),
+            };
+
+            match self.send_once(this_attempt) {
+                // asking again before the server said we may would only get throttled again, and the launcher can't wait that long
+                Err((e, Some(retry_after))) if retry_after > MAX_RETRY_DELAY => return Err(e),
+                Err((e, retry_after)) if e.is_retryable() && attempt < self.max_retries => {
+                    // exponential backoff, unless the server told us how long to wait
+                    let delay = retry_after.unwrap_or((RETRY_BASE_DELAY * 2u32.saturating_pow(attempt)).min(MAX_RETRY_DELAY));
+                    std::thread::sleep(delay);
+                    attempt += 1;
+                }
+                result => return result.map_err(|(e, _)| e),
+            }
+        }
+    }
+
+    // on failure, also returns how long the Retry-After header asked us to wait, if it was set
+    fn send_once(&self, request: reqwest::blocking::RequestBuilder) -> Result<reqwest::blocking::Response, (DeepLError, Option<Duration>)> {
+        let response = match request.send() {
+            Ok(response) => response,
+            // not getting through at all usually means there's no network, which the plugin treats differently from other failures
+            // a timeout only means this response was slow, so it's an ordinary failed request
+            Err(e) if e.is_connect() => return Err((DeepLError::Connection(e.to_string()), None)),
+            Err(e) => return Err((DeepLError::Request(e.to_string()), None)),
+        };
+
+        let status = response.status();
+        if !status.is_success() {
+            // only the delay-seconds form is supported, an http date falls back to the normal backoff
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse().ok())
+                .map(Duration::from_secs);
+            return Err((DeepLError::from_status(status, response.text().unwrap_or_default()), retry_after));
+        }
+
+        Ok(response)
+    }
+}
+
+impl DeepLBackend {
+    pub fn new(config: &quick_search_lib::Config) -> Self {
+        Self {
+            settings: RwLock::new(Arc::new(Settings::from_config(config))),
+        }
+    }
+
+    fn settings(&self) -> Arc<Settings> {
+        match self.settings.read() {
+            Ok(settings) => settings.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+}
+
+impl TranslationBackend for DeepLBackend {
+    fn translate(&self, request: &TranslateRequest) -> Result<TranslateResponse, DeepLError> {
+        // asked for on every request rather than being part of TranslateRequest, so it doesn't end up in the cache keys
+        #[derive(Serialize)]
+        struct Body<'a> {
+            #[serde(flatten)]
+            request: &'a TranslateRequest,
+            show_billed_characters: bool,
+        }
+
+        let settings = self.settings();
+        let body = Body {
+            request,
+            show_billed_characters: true,
+        };
+        settings.send_json(settings.client.post(settings.url("/v2/translate")).json(&body))
+    }
+
+    fn languages(&self, language_type: LanguageType) -> Result<Vec<Language>, DeepLError> {
+        let settings = self.settings();
+        settings.send_json(settings.client.get(settings.url("/v2/languages")).query(&[("type", language_type.as_str())]))
+    }
+
+    fn usage(&self) -> Result<UsageResponse, DeepLError> {
+        let settings = self.settings();
+        settings.send_json(settings.client.get(settings.url("/v2/usage")))
+    }
+
+    fn list_glossaries(&self) -> Result<Vec<GlossaryInfo>, DeepLError> {
+        let settings = self.settings();
+        settings
+            .send_json::<ListGlossariesResponse>(settings.client.get(settings.url("/v2/glossaries")))
+            .map(|response| response.glossaries)
+    }
+
+    fn create_glossary(&self, request: &CreateGlossaryRequest) -> Result<GlossaryInfo, DeepLError> {
+        let settings = self.settings();
+        settings.send_json(settings.client.post(settings.url("/v2/glossaries")).json(request))
+    }
+
+    fn delete_glossary(&self, glossary_id: &str) -> Result<(), DeepLError> {
+        let settings = self.settings();
+        settings
+            .send(settings.client.delete(settings.url(&format!("/v2/glossaries/{}", urlencoding::encode(glossary_id)))))
+            .map(|_| ())
+    }
+
+    fn reload_config(&self, config: &quick_search_lib::Config) {
+        let settings = Arc::new(Settings::from_config(config));
+        match self.settings.write() {
+            Ok(mut current) => *current = settings,
+            Err(poisoned) => *poisoned.into_inner() = settings,
+        }
+    }
+}
+
+// example request:
+// POST /v2/translate HTTP/2
+// Host: api-free.deepl.com
+// Authorization: DeepL-Auth-Key [yourAuthKey]
+// User-Agent: YourApp/1.2.3
+// Content-Length: 45
+// Content-Type: application/json
+
+// {"text":["Hello, world!"],"target_lang":"DE"}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TranslateRequest {
+    pub text: Vec<String>,
+    pub target_lang: TargetLanguageCode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_lang: Option<SourceLanguageCode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub formality: Option<Formality>,
+    // glossaries only work when the source language is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub glossary_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub split_sentences: Option<SplitSentences>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preserve_formatting: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag_handling: Option<TagHandling>,
+    // text around the query that helps with the translation, it isn't translated or billed itself
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+pub enum SplitSentences {
+    // translate the text as one sentence
+    #[serde(rename = "0")]
+    Off,
+    // split on punctuation and new lines, the api default
+    #[serde(rename = "1")]
+    On,
+    // split on punctuation only
+    #[serde(rename = "nonewlines")]
+    NoNewlines,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TagHandling {
+    Html,
+    Xml,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Formality {
+    Default,
+    More,
+    Less,
+    // like more and less, but they fall back to the default instead of failing for languages without formality
+    PreferMore,
+    PreferLess,
+}
+
+impl Formality {
+    pub fn guess_from_str(s: &str) -> Option<Self> {
+        Some(match s {
+            "default" => Formality::Default,
+            "more" => Formality::More,
+            "formal" => Formality::More,
+            "less" => Formality::Less,
+            "informal" => Formality::Less,
+            "prefer_more" => Formality::PreferMore,
+            "prefer_formal" => Formality::PreferMore,
+            "prefer_less" => Formality::PreferLess,
+            "prefer_informal" => Formality::PreferLess,
+            _ => return None,
+        })
+    }
+
+    // more and less are rejected by the api for languages that don't support formality
+    pub fn is_strict(&self) -> bool {
+        matches!(self, Formality::More | Formality::Less)
+    }
+}
+
+impl std::fmt::Display for Formality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Formality::Default => write!(f, "default"),
+            Formality::More => write!(f, "formal"),
+            Formality::Less => write!(f, "informal"),
+            Formality::PreferMore => write!(f, "preferably formal"),
+            Formality::PreferLess => write!(f, "preferably informal"),
+        }
+    }
+}
+
+// example response:
+// {
+//   "translations": [
+//     {
+//       "detected_source_language": "EN",
+//       "text": "Hallo, Welt!",
+//       "billed_characters": 13
+//     }
+//   ]
+// }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranslateResponse {
+    pub translations: Vec<TranslatedText>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranslatedText {
+    pub detected_source_language: SourceLanguageCode,
+    pub text: String,
+    // only there when show_billed_characters was set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub billed_characters: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum LanguageType {
+    Source,
+    Target,
+}
+
+impl LanguageType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LanguageType::Source => "source",
+            LanguageType::Target => "target",
+        }
+    }
+}
+
+// example request:
+// GET /v2/languages?type=target HTTP/2
+
+// example response:
+// [
+//   {
+//     "language": "DE",
+//     "name": "German",
+//     "supports_formality": true
+//   }
+// ]
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Language {
+    pub language: String,
+    pub name: String,
+    // only present for target languages
+    #[serde(default)]
+    pub supports_formality: bool,
+}
+
+// example request:
+// GET /v2/usage HTTP/2
+
+// example response:
+// {
+//   "character_count": 180118,
+//   "character_limit": 1250000,
+//   "document_count": 3,
+//   "document_limit": 10
+// }
+
+// the document and team document counts are only there for accounts that have those limits
+#[derive(Debug, Deserialize)]
+pub struct UsageResponse {
+    pub character_count: u64,
+    pub character_limit: u64,
+    #[serde(default)]
+    pub document_count: Option<u64>,
+    #[serde(default)]
+    pub document_limit: Option<u64>,
+    #[serde(default)]
+    pub team_document_count: Option<u64>,
+    #[serde(default)]
+    pub team_document_limit: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        thread::JoinHandle,
+    };
+
+    use abi_stable::std_types::RString;
+    use quick_search_lib::EntryType;
+
+    use super::*;
+
+    // answers one connection per response and hands back the request line of each
+    fn serve(responses: Vec<String>) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/", listener.local_addr().unwrap());
+
+        let handle = std::thread::spawn(move || {
+            responses
+                .into_iter()
+                .map(|response| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                    let mut request_line = String::new();
+                    reader.read_line(&mut request_line).unwrap();
+                    let mut content_length = 0;
+                    loop {
+                        let mut header = String::new();
+                        reader.read_line(&mut header).unwrap();
+                        if header.trim().is_empty() {
+                            break;
+                        }
+                        if let Some(length) = header.to_lowercase().strip_prefix("content-length:") {
+                            content_length = length.trim().parse().unwrap();
+                        }
+                    }
+                    reader.read_exact(&mut vec![0; content_length]).unwrap();
+
+                    stream.write_all(response.as_bytes()).unwrap();
+                    request_line.trim_end().to_owned()
+                })
+                .collect()
+        });
+
+        (base_url, handle)
+    }
+
+    fn response(status: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )
+    }
+
+    fn with_header(response: String, header: &str) -> String {
+        response.replacen("\r\n", &format!("\r\n{}\r\n", header), 1)
+    }
+
+    fn backend(base_url: &str) -> DeepLBackend {
+        retrying_backend(base_url, 0)
+    }
+
+    fn retrying_backend(base_url: &str, max_retries: u64) -> DeepLBackend {
+        let mut config = quick_search_lib::Config::new();
+        config.insert("DeepL Api Key".into(), EntryType::String { value: RString::from("key") });
+        config.insert("Custom API base URL".into(), EntryType::String { value: RString::from(base_url) });
+        config.insert(
+            "Max retries".into(),
+            EntryType::String {
+                value: max_retries.to_string().into(),
+            },
+        );
+        DeepLBackend::new(&config)
+    }
+
+    fn hello() -> TranslateRequest {
+        TranslateRequest {
+            text: vec!["Hello".to_owned()],
+            target_lang: TargetLanguageCode::DE,
+            source_lang: None,
+            formality: None,
+            glossary_id: None,
+            split_sentences: None,
+            preserve_formatting: None,
+            tag_handling: None,
+            context: None,
+        }
+    }
+
+    const TRANSLATION: &str = r#"{"translations":[{"detected_source_language":"EN","text":"Hallo"}]}"#;
+
+    #[test]
+    fn custom_base_url_replaces_the_deepl_endpoint() {
+        let (base_url, server) = serve(vec![response("200 OK", TRANSLATION)]);
+
+        let response = backend(&base_url).translate(&hello()).unwrap();
+        assert_eq!(response.translations[0].text, "Hallo");
+
+        // the trailing slash of the base url doesn't end up doubled in front of the path
+        assert_eq!(server.join().unwrap(), ["POST /v2/translate HTTP/1.1"]);
+    }
+
+    #[test]
+    fn error_statuses_become_deepl_errors() {
+        let (base_url, server) = serve(vec![
+            response("403 Forbidden", ""),
+            response("456 Quota Exceeded", ""),
+            response("503 Service Unavailable", ""),
+        ]);
+        let backend = backend(&base_url);
+
+        assert!(matches!(backend.translate(&hello()), Err(DeepLError::InvalidApiKey)));
+        assert!(matches!(backend.translate(&hello()), Err(DeepLError::QuotaExceeded)));
+        assert!(matches!(backend.translate(&hello()), Err(DeepLError::ServerError(503))));
+        assert_eq!(server.join().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn nothing_is_sent_without_an_api_key() {
+        let backend = backend("http://127.0.0.1:1");
+        backend.reload_config(&quick_search_lib::Config::new());
+
+        assert!(matches!(backend.translate(&hello()), Err(DeepLError::NoApiKey)));
+    }
+
+    #[test]
+    fn unreachable_server_is_a_connection_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        assert!(matches!(backend(&base_url).translate(&hello()), Err(DeepLError::Connection(_))));
+    }
+
+    // the server only answers as often as the client is expected to ask, an extra attempt would fail to connect
+
+    #[test]
+    fn retries_throttled_requests() {
+        let (base_url, server) = serve(vec![response("429 Too Many Requests", ""), response("200 OK", TRANSLATION)]);
+
+        let response = retrying_backend(&base_url, 2).translate(&hello()).unwrap();
+        assert_eq!(response.translations[0].text, "Hallo");
+        assert_eq!(server.join().unwrap(), ["POST /v2/translate HTTP/1.1", "POST /v2/translate HTTP/1.1"]);
+    }
+
+    #[test]
+    fn gives_up_after_the_last_retry() {
+        let (base_url, server) = serve(vec![response("503 Service Unavailable", ""), response("502 Bad Gateway", "")]);
+
+        assert!(matches!(retrying_backend(&base_url, 1).translate(&hello()), Err(DeepLError::ServerError(502))));
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn never_retries_a_rejected_key_or_quota() {
+        let (base_url, server) = serve(vec![response("403 Forbidden", ""), response("456 Quota Exceeded", "")]);
+        let backend = retrying_backend(&base_url, 2);
+
+        assert!(matches!(backend.translate(&hello()), Err(DeepLError::InvalidApiKey)));
+        assert!(matches!(backend.translate(&hello()), Err(DeepLError::QuotaExceeded)));
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn waits_as_long_as_retry_after_asks() {
+        let (base_url, server) = serve(vec![with_header(response("429 Too Many Requests", ""), "Retry-After: 1"), response("200 OK", TRANSLATION)]);
+
+        let started = std::time::Instant::now();
+        assert!(retrying_backend(&base_url, 1).translate(&hello()).is_ok());
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn gives_up_when_retry_after_is_too_long_to_wait() {
+        let (base_url, server) = serve(vec![with_header(response("503 Service Unavailable", ""), "Retry-After: 60")]);
+
+        let started = std::time::Instant::now();
+        assert!(retrying_backend(&base_url, 2).translate(&hello()).is_err());
+        assert!(started.elapsed() < MAX_RETRY_DELAY);
+        assert_eq!(server.join().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn slow_response_is_not_a_connection_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            // takes the request but never answers it
+            let (stream, _) = listener.accept().unwrap();
+            std::thread::sleep(Duration::from_secs(1));
+            drop(stream);
+        });
+
+        let mut config = quick_search_lib::Config::new();
+        config.insert("DeepL Api Key".into(), EntryType::String { value: RString::from("key") });
+        config.insert(
+            "Custom API base URL".into(),
+            EntryType::String {
+                value: RString::from(base_url.as_str()),
+            },
+        );
+        config.insert("Max retries".into(), EntryType::String { value: "0".into() });
+        config.insert("Request timeout (ms)".into(), EntryType::String { value: "200".into() });
+
+        assert!(matches!(DeepLBackend::new(&config).translate(&hello()), Err(DeepLError::Request(_))));
+        server.join().unwrap();
+    }
+}
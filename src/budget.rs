@@ -0,0 +1,220 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// billed characters per calendar month (utc), kept on disk so the budget holds across restarts and launcher instances
+// every instance reads the file again before reserving and recording, so their counts add up
+pub struct CharacterBudget {
+    path: Option<PathBuf>,
+    // e.g. "2026-10" -> 123456, older months are kept as a record of past usage
+    months: BTreeMap<String, u64>,
+    // characters of requests that are sent but not answered yet, so parallel requests can't overshoot the budget together
+    pending: u64,
+}
+
+impl CharacterBudget {
+    pub fn new() -> Self {
+        Self {
+            path: None,
+            months: BTreeMap::new(),
+            pending: 0,
+        }
+    }
+
+    // the file isn't read yet, see refresh
+    pub fn at(path: PathBuf) -> Self {
+        Self {
+            path: Some(path),
+            months: BTreeMap::new(),
+            pending: 0,
+        }
+    }
+
+    // picks up what other launcher instances recorded since the file was last read
+    // a file that can't be parsed is left alone, so it can be fixed by hand instead of being overwritten with this instance's count
+    pub fn refresh(&mut self) -> std::io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let months: BTreeMap<String, u64> = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+
+        // the larger count wins, an older copy of the file can't make characters billed by this instance disappear
+        for (month, billed) in months {
+            let current = self.months.entry(month).or_insert(0);
+            *current = (*current).max(billed);
+        }
+
+        Ok(())
+    }
+
+    // billed characters this month
+    pub fn used(&self) -> u64 {
+        self.months.get(&current_month()).copied().unwrap_or(0)
+    }
+
+    // sets `characters` aside for a request, or gives back how many characters are already used when they don't fit into `budget`
+    // a budget of 0 means there is no budget
+    pub fn reserve(&mut self, characters: u64, budget: u64) -> Result<(), u64> {
+        if budget > 0 {
+            // a file that can't be read is reported by record, until then the count of this instance is all there is
+            let _ = self.refresh();
+        }
+
+        let used = self.used();
+        if budget > 0 && used + self.pending + characters > budget {
+            return Err(used);
+        }
+
+        self.pending += characters;
+        Ok(())
+    }
+
+    // the request failed, nothing was billed
+    pub fn release(&mut self, reserved: u64) {
+        self.pending = self.pending.saturating_sub(reserved);
+    }
+
+    pub fn record(&mut self, reserved: u64, billed: u64) -> std::io::Result<()> {
+        self.release(reserved);
+        // read before adding, otherwise the characters billed by other instances in the meantime would be overwritten
+        let refreshed = self.refresh();
+        *self.months.entry(current_month()).or_insert(0) += billed;
+        refreshed?;
+        self.save()
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let temp_path = path.with_extension("tmp");
+        std::fs::write(&temp_path, serde_json::to_string_pretty(&self.months)?)?;
+        std::fs::rename(&temp_path, path)
+    }
+}
+
+// "yyyy-mm" of the current utc month, worked out from the days since the epoch to avoid pulling in a date crate
+fn current_month() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() / 86400).unwrap_or(0) as i64;
+
+    // civil_from_days from http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}", year, month)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("deepl-budget-{}-{}.json", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn pending_requests_count_against_the_budget() {
+        let mut budget = CharacterBudget::new();
+        budget.reserve(60, 100).unwrap();
+        assert_eq!(budget.reserve(50, 100), Err(0));
+
+        budget.release(60);
+        budget.reserve(50, 100).unwrap();
+        budget.record(50, 40).unwrap();
+        assert_eq!(budget.used(), 40);
+        assert_eq!(budget.reserve(61, 100), Err(40));
+
+        // 0 is no budget at all
+        budget.reserve(1_000_000, 0).unwrap();
+    }
+
+    #[test]
+    fn billed_characters_survive_a_restart() {
+        let path = temp_path("restart");
+        let mut budget = CharacterBudget::at(path.clone());
+        budget.reserve(100, 0).unwrap();
+        budget.record(100, 90).unwrap();
+
+        let mut reloaded = CharacterBudget::at(path.clone());
+        reloaded.refresh().unwrap();
+        assert_eq!(reloaded.used(), 90);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn current_month_is_year_and_month() {
+        let month = current_month();
+        assert_eq!(month.len(), 7);
+        assert_eq!(&month[4..5], "-");
+        assert!(month[..4].parse::<u32>().unwrap() >= 2024);
+        assert!((1..=12).contains(&month[5..].parse::<u32>().unwrap()));
+    }
+
+    #[test]
+    fn instances_add_up() {
+        let path = temp_path("instances");
+        let mut first = CharacterBudget::at(path.clone());
+        let mut second = CharacterBudget::at(path.clone());
+        first.refresh().unwrap();
+        second.refresh().unwrap();
+
+        first.reserve(100, 0).unwrap();
+        first.record(100, 100).unwrap();
+        second.reserve(50, 0).unwrap();
+        second.record(50, 50).unwrap();
+        assert_eq!(second.used(), 150);
+
+        // the budget holds against what the other instance billed
+        assert_eq!(first.reserve(100, 200), Err(150));
+        first.reserve(50, 200).unwrap();
+
+        let mut reloaded = CharacterBudget::at(path.clone());
+        reloaded.refresh().unwrap();
+        assert_eq!(reloaded.used(), 150);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn corrupt_file_is_left_alone() {
+        let path = temp_path("corrupt");
+        std::fs::write(&path, "{ not json").unwrap();
+
+        let mut budget = CharacterBudget::at(path.clone());
+        assert!(budget.refresh().is_err());
+        budget.reserve(100, 0).unwrap();
+        assert!(budget.record(100, 100).is_err());
+        assert_eq!(budget.used(), 100);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{ not json");
+
+        // once it's fixed by hand, counting goes on from what's in it
+        std::fs::write(&path, format!("{{\"{}\": 1000}}", current_month())).unwrap();
+        budget.record(0, 10).unwrap();
+        assert_eq!(budget.used(), 1010);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
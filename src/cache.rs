@@ -0,0 +1,395 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{TranslateRequest, TranslateResponse};
+
+// the launcher searches on every keystroke, so remember recent translations instead of paying for them again
+pub struct TranslationCache {
+    capacity: usize,
+    // zero means entries never expire
+    ttl: Duration,
+    entries: HashMap<String, CacheEntry>,
+    // bumped on every access, the entry with the lowest value is the least recently used one
+    tick: u64,
+    // translations that survive restarts, consulted when the in-memory cache misses
+    disk: Option<DiskCache>,
+}
+
+struct CacheEntry {
+    response: TranslateResponse,
+    created: SystemTime,
+    last_used: u64,
+}
+
+impl TranslationCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            entries: HashMap::new(),
+            tick: 0,
+            disk: None,
+        }
+    }
+
+    pub fn set_disk_cache(&mut self, disk: Option<DiskCache>) {
+        self.disk = disk;
+    }
+
+    pub fn resize(&mut self, capacity: usize, ttl: Duration) {
+        self.capacity = capacity;
+        self.ttl = ttl;
+        while self.entries.len() > self.capacity {
+            self.evict();
+        }
+    }
+
+    // expired entries are still handed out when `allow_expired` is set, an old translation beats none at all when offline
+    pub fn get(&mut self, key: &str, allow_expired: bool) -> Option<TranslateResponse> {
+        let expired = match self.entries.get(key) {
+            Some(entry) => self.is_expired(entry.created),
+            None => return self.get_from_disk(key, allow_expired),
+        };
+
+        // an expired entry stays until it is replaced or evicted, it's still needed if deepl can't be reached for a fresh one
+        if expired && !allow_expired {
+            return None;
+        }
+
+        self.tick += 1;
+        let tick = self.tick;
+        self.entries.get_mut(key).map(|entry| {
+            entry.last_used = tick;
+            entry.response.clone()
+        })
+    }
+
+    fn get_from_disk(&mut self, key: &str, allow_expired: bool) -> Option<TranslateResponse> {
+        let (response, created) = match self.disk.as_ref().and_then(|disk| disk.get(key)) {
+            Some(stored) => (stored.response.clone(), UNIX_EPOCH + Duration::from_secs(stored.created)),
+            None => return None,
+        };
+
+        if self.is_expired(created) && !allow_expired {
+            return None;
+        }
+
+        // promote it so the next lookup doesn't have to go through the disk cache again
+        self.insert_in_memory(key.to_owned(), response.clone(), created);
+        Some(response)
+    }
+
+    pub fn insert(&mut self, key: String, response: TranslateResponse) -> std::io::Result<()> {
+        let created = SystemTime::now();
+
+        if let Some(disk) = &mut self.disk {
+            disk.insert(key.clone(), response.clone(), created)?;
+        }
+
+        self.insert_in_memory(key, response, created);
+        Ok(())
+    }
+
+    fn insert_in_memory(&mut self, key: String, response: TranslateResponse, created: SystemTime) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if !self.entries.contains_key(&key) {
+            while self.entries.len() >= self.capacity {
+                self.evict();
+            }
+        }
+
+        self.tick += 1;
+        self.entries.insert(
+            key,
+            CacheEntry {
+                response,
+                created,
+                last_used: self.tick,
+            },
+        );
+    }
+
+    fn is_expired(&self, created: SystemTime) -> bool {
+        // a clock that went backwards counts as fresh rather than expired
+        !self.ttl.is_zero() && created.elapsed().map(|age| age > self.ttl).unwrap_or(false)
+    }
+
+    fn evict(&mut self) {
+        let oldest = self.entries.iter().min_by_key(|(_, entry)| entry.last_used).map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            self.entries.remove(&key);
+        }
+    }
+}
+
+// the whole request is the key, so every option that changes the translation (languages, formality, glossary, ...) is part of it
+pub fn cache_key(request: &TranslateRequest) -> Option<String> {
+    serde_json::to_string(request).ok()
+}
+
+// an append-only log of json lines, one per translation, later lines win over earlier ones with the same key
+// the whole file is read once when the cache is enabled, and rewritten without the stale lines once it grows to twice the size limit
+pub struct DiskCache {
+    path: PathBuf,
+    max_entries: usize,
+    entries: HashMap<String, StoredEntry>,
+    // lines currently in the file, including the ones that have been overwritten or evicted since
+    lines: usize,
+    // write order, the entry with the lowest value is evicted first
+    sequence: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredEntry {
+    key: String,
+    response: TranslateResponse,
+    // seconds since the unix epoch
+    created: u64,
+    #[serde(skip)]
+    sequence: u64,
+}
+
+impl DiskCache {
+    pub fn load(path: PathBuf, max_entries: usize) -> std::io::Result<Self> {
+        let mut this = Self {
+            path,
+            max_entries,
+            entries: HashMap::new(),
+            lines: 0,
+            sequence: 0,
+        };
+
+        let file = match std::fs::File::open(&this.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(this),
+            Err(e) => return Err(e),
+        };
+
+        for line in std::io::BufReader::new(file).lines() {
+            let line = line?;
+            this.lines += 1;
+
+            // a half written line from a crash shouldn't throw away everything else
+            if let Ok(mut entry) = serde_json::from_str::<StoredEntry>(&line) {
+                this.sequence += 1;
+                entry.sequence = this.sequence;
+                this.entries.insert(entry.key.clone(), entry);
+            }
+        }
+
+        while this.entries.len() > this.max_entries {
+            this.evict();
+        }
+
+        if this.lines > this.entries.len() * 2 {
+            this.compact()?;
+        }
+
+        Ok(this)
+    }
+
+    fn get(&self, key: &str) -> Option<&StoredEntry> {
+        self.entries.get(key)
+    }
+
+    fn insert(&mut self, key: String, response: TranslateResponse, created: SystemTime) -> std::io::Result<()> {
+        if self.max_entries == 0 {
+            return Ok(());
+        }
+
+        self.sequence += 1;
+        let entry = StoredEntry {
+            key: key.clone(),
+            response,
+            created: created.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            sequence: self.sequence,
+        };
+
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        self.lines += 1;
+
+        self.entries.insert(key, entry);
+        while self.entries.len() > self.max_entries {
+            self.evict();
+        }
+
+        if self.lines > self.max_entries * 2 {
+            self.compact()?;
+        }
+
+        Ok(())
+    }
+
+    fn evict(&mut self) {
+        let oldest = self.entries.iter().min_by_key(|(_, entry)| entry.sequence).map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            self.entries.remove(&key);
+        }
+    }
+
+    // rewrite the file with only the live entries, through a temporary file so a crash can't leave it half written
+    fn compact(&mut self) -> std::io::Result<()> {
+        let mut entries = self.entries.values().collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.sequence);
+
+        let mut contents = String::new();
+        for entry in &entries {
+            contents.push_str(&serde_json::to_string(entry)?);
+            contents.push('\n');
+        }
+
+        let temp_path = self.path.with_extension("tmp");
+        std::fs::write(&temp_path, contents)?;
+        std::fs::rename(&temp_path, &self.path)?;
+
+        self.lines = entries.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(text: &str) -> TranslateResponse {
+        serde_json::from_value(serde_json::json!({ "translations": [{ "detected_source_language": "EN", "text": text }] })).unwrap()
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("deepl-cache-{}-{}.jsonl", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn lines(path: &PathBuf) -> usize {
+        std::fs::read_to_string(path).unwrap().lines().count()
+    }
+
+    fn text(response: Option<TranslateResponse>) -> Option<String> {
+        response.map(|response| response.translations[0].text.clone())
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_entry() {
+        let mut cache = TranslationCache::new(2, Duration::ZERO);
+        cache.insert("a".to_owned(), response("A")).unwrap();
+        cache.insert("b".to_owned(), response("B")).unwrap();
+        assert_eq!(text(cache.get("a", false)), Some("A".to_owned()));
+
+        cache.insert("c".to_owned(), response("C")).unwrap();
+        assert_eq!(text(cache.get("b", false)), None);
+        assert_eq!(text(cache.get("a", false)), Some("A".to_owned()));
+        assert_eq!(text(cache.get("c", false)), Some("C".to_owned()));
+    }
+
+    #[test]
+    fn expires_entries_after_the_ttl() {
+        let mut cache = TranslationCache::new(2, Duration::from_millis(20));
+        cache.insert("a".to_owned(), response("A")).unwrap();
+        assert_eq!(text(cache.get("a", false)), Some("A".to_owned()));
+
+        std::thread::sleep(Duration::from_millis(40));
+        // still there for when deepl can't be reached
+        assert_eq!(text(cache.get("a", true)), Some("A".to_owned()));
+        assert_eq!(text(cache.get("a", false)), None);
+        // and missing a fresh one doesn't throw it away
+        assert_eq!(text(cache.get("a", true)), Some("A".to_owned()));
+    }
+
+    #[test]
+    fn shrinking_evicts_and_zero_capacity_disables() {
+        let mut cache = TranslationCache::new(3, Duration::ZERO);
+        for key in ["a", "b", "c"] {
+            cache.insert(key.to_owned(), response(key)).unwrap();
+        }
+
+        cache.resize(1, Duration::ZERO);
+        assert_eq!(text(cache.get("c", false)), Some("c".to_owned()));
+        assert_eq!(text(cache.get("a", false)), None);
+
+        cache.resize(0, Duration::ZERO);
+        cache.insert("d".to_owned(), response("d")).unwrap();
+        assert_eq!(text(cache.get("d", false)), None);
+    }
+
+    #[test]
+    fn disk_cache_survives_a_restart() {
+        let path = temp_path("restart");
+        let mut disk = DiskCache::load(path.clone(), 10).unwrap();
+        disk.insert("a".to_owned(), response("A"), SystemTime::now()).unwrap();
+        disk.insert("b".to_owned(), response("B"), SystemTime::now()).unwrap();
+        // later lines win
+        disk.insert("a".to_owned(), response("A2"), SystemTime::now()).unwrap();
+
+        let mut cache = TranslationCache::new(10, Duration::ZERO);
+        cache.set_disk_cache(Some(DiskCache::load(path.clone(), 10).unwrap()));
+        assert_eq!(text(cache.get("a", false)), Some("A2".to_owned()));
+        assert_eq!(text(cache.get("b", false)), Some("B".to_owned()));
+        assert_eq!(text(cache.get("c", false)), None);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn disk_cache_skips_a_truncated_line() {
+        let path = temp_path("truncated");
+        let mut disk = DiskCache::load(path.clone(), 10).unwrap();
+        disk.insert("a".to_owned(), response("A"), SystemTime::now()).unwrap();
+        let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "{{\"key\":\"b\",\"resp").unwrap();
+
+        let disk = DiskCache::load(path.clone(), 10).unwrap();
+        assert_eq!(disk.get("a").map(|entry| entry.response.translations[0].text.clone()), Some("A".to_owned()));
+        assert!(disk.get("b").is_none());
+        assert_eq!(disk.lines, 2);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn disk_cache_evicts_the_oldest_entries() {
+        let path = temp_path("evict");
+        let mut disk = DiskCache::load(path.clone(), 2).unwrap();
+        for key in ["a", "b", "c"] {
+            disk.insert(key.to_owned(), response(key), SystemTime::now()).unwrap();
+        }
+        assert!(disk.get("a").is_none());
+
+        // the file still has all three lines, loading it keeps the newest two
+        let disk = DiskCache::load(path.clone(), 2).unwrap();
+        assert!(disk.get("a").is_none());
+        assert!(disk.get("b").is_some() && disk.get("c").is_some());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn disk_cache_compacts_at_twice_the_limit() {
+        let path = temp_path("compact");
+        let mut disk = DiskCache::load(path.clone(), 2).unwrap();
+        for _ in 0..4 {
+            disk.insert("a".to_owned(), response("A"), SystemTime::now()).unwrap();
+        }
+        assert_eq!(lines(&path), 4);
+
+        disk.insert("a".to_owned(), response("A"), SystemTime::now()).unwrap();
+        assert_eq!(lines(&path), 1);
+        assert_eq!(disk.lines, 1);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
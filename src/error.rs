@@ -0,0 +1,159 @@
+use crate::TargetLanguageCode;
+
+// every way a query can fail, from parsing it to talking to the api
+#[derive(Debug)]
+pub enum DeepLError {
+    NoApiKey,
+    NoQuery,
+    NoTargetLanguage,
+    TooManyArrows,
+    InvalidSourceLanguage(String),
+    InvalidTargetLanguage(String),
+    // a language name that fits several languages, with the (code, name) candidates
+    AmbiguousLanguage(String, Vec<(String, String)>),
+    // a language name that fits none, with the (code, name) of the ones it comes close to
+    MisspelledLanguage(String, Vec<(String, String)>),
+    InvalidFormality(String),
+    UnknownFlag(String),
+    // a flag and the value it can't take
+    InvalidFlagValue(String, String),
+    // a [ without a ]
+    UnclosedOptions,
+    // "My languages" isn't a primary and a different secondary language
+    InvalidMyLanguages(String),
+    // an error in part of the query, with the query and that part marked
+    InQuery(Box<DeepLError>, String),
+    FormalityNotSupported(TargetLanguageCode),
+    // a glossary command or term file that couldn't be used
+    Glossary(String),
+    // the monthly character budget (used, budget) doesn't leave room for the request
+    BudgetExceeded(u64, u64),
+    // the server couldn't be reached at all
+    Connection(String),
+    // offline and the translation isn't cached
+    Offline,
+    // the request never got a response
+    Request(String),
+    // the response body wasn't what we expected
+    Parse(String),
+    // 403
+    InvalidApiKey,
+    // 456
+    QuotaExceeded,
+    // 429
+    TooManyRequests,
+    // 5xx
+    ServerError(u16),
+    UnexpectedStatus(u16, String),
+}
+
+impl DeepLError {
+    pub fn from_status(status: reqwest::StatusCode, body: String) -> Self {
+        match status.as_u16() {
+            403 => DeepLError::InvalidApiKey,
+            456 => DeepLError::QuotaExceeded,
+            429 => DeepLError::TooManyRequests,
+            code @ 500..=599 => DeepLError::ServerError(code),
+            code => DeepLError::UnexpectedStatus(code, body),
+        }
+    }
+
+    // throttling and server errors are usually gone after a moment, anything else (like a bad key or no quota left) won't be fixed by trying again
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, DeepLError::TooManyRequests | DeepLError::ServerError(_))
+    }
+
+    // the error itself, without the query it was found in
+    pub fn kind(&self) -> &DeepLError {
+        match self {
+            DeepLError::InQuery(e, _) => e.kind(),
+            e => e,
+        }
+    }
+
+    // short title for the error result, the Display impl is used as its context
+    pub fn title(&self) -> &'static str {
+        match self {
+            DeepLError::InQuery(e, _) => e.title(),
+            DeepLError::NoApiKey => "No API key",
+            DeepLError::NoQuery => "No query",
+            DeepLError::NoTargetLanguage
+            | DeepLError::TooManyArrows
+            | DeepLError::InvalidSourceLanguage(_)
+            | DeepLError::InvalidTargetLanguage(_)
+            | DeepLError::MisspelledLanguage(_, _)
+            | DeepLError::InvalidFormality(_) => "Invalid query",
+            DeepLError::UnknownFlag(_) | DeepLError::InvalidFlagValue(_, _) | DeepLError::UnclosedOptions => "Invalid option",
+            DeepLError::InvalidMyLanguages(_) => "Invalid config",
+            DeepLError::AmbiguousLanguage(_, _) => "Ambiguous language",
+            DeepLError::FormalityNotSupported(_) => "Formality not supported",
+            DeepLError::Glossary(_) => "Glossary",
+            DeepLError::BudgetExceeded(_, _) => "Character budget reached",
+            DeepLError::Connection(_) => "No connection",
+            DeepLError::Offline => "Offline",
+            DeepLError::Request(_) => "Request failed",
+            DeepLError::Parse(_) => "Response failed",
+            DeepLError::InvalidApiKey => "Invalid API key",
+            DeepLError::QuotaExceeded => "Quota exceeded",
+            DeepLError::TooManyRequests => "Too many requests",
+            DeepLError::ServerError(_) => "DeepL server error",
+            DeepLError::UnexpectedStatus(_, _) => "Unexpected response",
+        }
+    }
+}
+
+impl std::fmt::Display for DeepLError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeepLError::NoApiKey => write!(f, "No DeepL API key was provided, set one in the plugin config"),
+            DeepLError::NoQuery => write!(f, "No query was provided"),
+            DeepLError::NoTargetLanguage => write!(f, "No target language code"),
+            DeepLError::TooManyArrows => write!(f, "Too many arrows, use <source> -> <target>: <text>"),
+            DeepLError::InvalidSourceLanguage(code) if code.is_empty() => write!(f, "No source language code before the arrow, leave the arrow out to detect the language"),
+            DeepLError::InvalidSourceLanguage(code) => write!(f, "Invalid source language code: {}", code),
+            DeepLError::InvalidTargetLanguage(code) => write!(f, "Invalid target language code: {}", code),
+            DeepLError::AmbiguousLanguage(input, candidates) => write!(
+                f,
+                "{} could be any of {}",
+                input,
+                candidates.iter().map(|(code, name)| format!("{} ({})", name, code)).collect::<Vec<_>>().join(", ")
+            ),
+            DeepLError::MisspelledLanguage(input, suggestions) => write!(
+                f,
+                "Unknown language: {}, did you mean {}?",
+                input,
+                suggestions.iter().map(|(code, name)| format!("{} ({})", name, code)).collect::<Vec<_>>().join(", ")
+            ),
+            DeepLError::UnknownFlag(flag) => write!(
+                f,
+                "Unknown option: {}, use formal, informal, prefer_more, prefer_less, both, glossary=<name>, noglossary, nosplit, split=<0|1|nonewlines>, preserve, html, xml, back or context=<text>",
+                flag
+            ),
+            DeepLError::InvalidFlagValue(flag, value) if value.is_empty() => write!(f, "The {} option needs a value, e.g. {}=<value>", flag, flag),
+            DeepLError::InvalidFlagValue(flag, value) => write!(f, "Invalid value for the {} option: {}", flag, value),
+            DeepLError::UnclosedOptions => write!(f, "The options are missing a closing ]"),
+            DeepLError::InvalidMyLanguages(languages) => write!(f, "\"My languages\" needs a primary and a different secondary language, e.g. EN-US, DE, not {}", languages),
+            DeepLError::InQuery(e, marked) => write!(f, "{} in {}", e, marked),
+            DeepLError::InvalidFormality(formality) if formality.is_empty() => write!(f, "No formality after !, use one of more, less, prefer_more, prefer_less, both"),
+            DeepLError::InvalidFormality(formality) => write!(f, "Invalid formality: {}, use one of more, less, prefer_more, prefer_less, both", formality),
+            DeepLError::FormalityNotSupported(target) => write!(f, "{} doesn't support formality, use prefer_more or prefer_less to fall back to the default", target),
+            DeepLError::Glossary(e) => write!(f, "{}", e),
+            DeepLError::BudgetExceeded(used, budget) => write!(
+                f,
+                "{} of the {} characters budgeted for this month are used, raise \"Monthly character budget\" to translate more",
+                used, budget
+            ),
+            DeepLError::Connection(e) => write!(f, "Failed to reach DeepL, only cached translations are available until it is back: {}", e),
+            DeepLError::Offline => write!(f, "Offline and this translation isn't cached"),
+            DeepLError::Request(e) => write!(f, "Failed to send request: {}", e),
+            DeepLError::Parse(e) => write!(f, "Failed to parse response: {}", e),
+            DeepLError::InvalidApiKey => write!(f, "DeepL rejected the API key (403), check the key and the \"Use free tier\" setting"),
+            DeepLError::QuotaExceeded => write!(f, "The character quota for this billing period has been used up (456)"),
+            DeepLError::TooManyRequests => write!(f, "DeepL is throttling requests (429), try again in a moment"),
+            DeepLError::ServerError(code) => write!(f, "DeepL is having problems ({}), try again later", code),
+            DeepLError::UnexpectedStatus(code, body) => write!(f, "DeepL responded with {}: {}", code, body),
+        }
+    }
+}
+
+impl std::error::Error for DeepLError {}
@@ -0,0 +1,585 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use quick_search_lib::SearchResult;
+
+use crate::{backend::TranslateRequest, data_dir, error::DeepLError, Action, DeepL, SourceLanguageCode, TargetLanguageCode};
+
+// example request:
+// POST /v2/glossaries HTTP/2
+// Content-Type: application/json
+
+// {"name":"product","source_lang":"en","target_lang":"de","entries":"Hello\tHallo","entries_format":"tsv"}
+
+#[derive(Debug, Serialize)]
+pub struct CreateGlossaryRequest {
+    pub name: String,
+    pub source_lang: String,
+    pub target_lang: String,
+    pub entries: String,
+    pub entries_format: EntriesFormat,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EntriesFormat {
+    Tsv,
+    Csv,
+}
+
+// example response (also the shape of every entry when listing glossaries):
+// {
+//   "glossary_id": "def3a26b-3e84-45b3-84ae-0c0aaf3525f7",
+//   "name": "product",
+//   "ready": true,
+//   "source_lang": "en",
+//   "target_lang": "de",
+//   "creation_time": "2021-08-03T14:16:18.329Z",
+//   "entry_count": 1
+// }
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GlossaryInfo {
+    pub glossary_id: String,
+    pub name: String,
+    pub ready: bool,
+    pub source_lang: String,
+    pub target_lang: String,
+    pub entry_count: u64,
+}
+
+// example request:
+// GET /v2/glossaries HTTP/2
+
+// example response:
+// {
+//   "glossaries": [ ... ]
+// }
+
+#[derive(Debug, Deserialize)]
+pub struct ListGlossariesResponse {
+    pub glossaries: Vec<GlossaryInfo>,
+}
+
+// glossaries only know about base languages, so EN-GB and EN-US both use the en glossaries
+pub fn glossary_language(code: &str) -> String {
+    code.split('-').next().unwrap_or_default().trim().to_lowercase()
+}
+
+// the ready glossary for a source/target pair, if there is one
+pub fn find_glossary<'a>(glossaries: &'a [GlossaryInfo], source: &SourceLanguageCode, target: &TargetLanguageCode) -> Option<&'a GlossaryInfo> {
+    let source = glossary_language(&source.code());
+    let target = glossary_language(&target.code());
+    glossaries
+        .iter()
+        .find(|glossary| glossary.ready && glossary.source_lang.eq_ignore_ascii_case(&source) && glossary.target_lang.eq_ignore_ascii_case(&target))
+}
+
+// term files are named after their language pair, e.g. en-de.tsv holds english terms and their german translations
+pub fn language_pair_from_path(path: &Path) -> Option<(String, String)> {
+    let stem = path.file_stem()?.to_str()?;
+    let (source, target) = stem.split_once('-')?;
+    let (source, target) = (glossary_language(source), glossary_language(target));
+    if source.is_empty() || target.is_empty() {
+        return None;
+    }
+    Some((source, target))
+}
+
+// reads a term file into the entries of a create request, csv files are sent as csv and everything else as tsv
+pub fn read_term_file(path: &Path) -> std::io::Result<(String, EntriesFormat)> {
+    let entries = std::fs::read_to_string(path)?;
+    let format = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) if extension.eq_ignore_ascii_case("csv") => EntriesFormat::Csv,
+        _ => EntriesFormat::Tsv,
+    };
+    // deepl rejects empty lines, and files written on windows shouldn't leave a \r on every target term
+    let entries = entries
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+    Ok((entries, format))
+}
+
+// glossaries created from the term files in the glossary directory, remembered across restarts
+// so unchanged files don't have to be uploaded again and searches know their ids without listing the account's glossaries
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SyncedGlossaries {
+    // hash of the api key and endpoint the glossaries were created with, ids from another account are useless
+    account: String,
+    // keyed by file name
+    files: HashMap<String, SyncedGlossary>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SyncedGlossary {
+    // hash of the entries that were uploaded
+    hash: String,
+    glossary_id: String,
+    source_lang: String,
+    target_lang: String,
+}
+
+impl SyncedGlossaries {
+    // a missing or broken file just means everything gets uploaded again
+    pub fn load(path: &Path, account: &str) -> Self {
+        match std::fs::read_to_string(path).ok().and_then(|contents| serde_json::from_str::<Self>(&contents).ok()) {
+            Some(synced) if synced.account == account => synced,
+            _ => Self::empty(account),
+        }
+    }
+
+    fn empty(account: &str) -> Self {
+        Self {
+            account: account.to_owned(),
+            files: HashMap::new(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let temp_path = path.with_extension("tmp");
+        std::fs::write(&temp_path, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&temp_path, path)
+    }
+
+    // drops every file whose glossary id is `gone`, so it gets uploaded again on the next sync, true if there was one
+    fn forget(&mut self, gone: impl Fn(&str) -> bool) -> bool {
+        let before = self.files.len();
+        self.files.retain(|_, glossary| !gone(&glossary.glossary_id));
+        self.files.len() != before
+    }
+
+    pub fn find(&self, source: &SourceLanguageCode, target: &TargetLanguageCode) -> Option<&str> {
+        let source = glossary_language(&source.code());
+        let target = glossary_language(&target.code());
+        self.files
+            .values()
+            .find(|glossary| glossary.source_lang == source && glossary.target_lang == target)
+            .map(|glossary| glossary.glossary_id.as_str())
+    }
+}
+
+// 64 bit fnv-1a, std's hasher isn't guaranteed to give the same result across rust versions and these hashes are stored on disk
+pub fn content_hash(content: &str) -> String {
+    let hash = content.bytes().fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+    format!("{:016x}", hash)
+}
+
+impl DeepL {
+    // the account's glossaries, fetched on first use and kept until they are changed or the config is reloaded
+    fn glossaries(&self) -> Vec<GlossaryInfo> {
+        let mut glossaries = match self.glossaries.lock() {
+            Ok(glossaries) => glossaries,
+            Err(_) => return vec![],
+        };
+
+        if let Some(glossaries) = glossaries.as_ref() {
+            return glossaries.clone();
+        }
+
+        if self.is_offline() {
+            return vec![];
+        }
+
+        // remember failures as no glossaries, otherwise every keystroke would ask again
+        let fetched = match self.backend.list_glossaries() {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                self.logger.warn(&format!("Failed to list glossaries: {}", e));
+                vec![]
+            }
+        };
+
+        *glossaries = Some(fetched.clone());
+        fetched
+    }
+
+    pub(crate) fn forget_glossaries(&self) {
+        if let Ok(mut glossaries) = self.glossaries.lock() {
+            *glossaries = None;
+        }
+    }
+
+    // deepl only applies a glossary when the source language is given, so requests without one are left alone
+    // glossaries synced from the glossary directory win over the account's other glossaries for the same pair
+    pub(crate) fn attach_glossaries(&self, requests: &mut [TranslateRequest]) {
+        let use_glossaries = self.config.get("Use glossaries").and_then(|entry| entry.as_bool()).unwrap_or(true);
+        if !use_glossaries {
+            return;
+        }
+
+        if let Ok(synced) = self.synced_glossaries.lock() {
+            for request in requests.iter_mut().filter(|request| request.glossary_id.is_none()) {
+                if let Some(source) = &request.source_lang {
+                    request.glossary_id = synced.find(source, &request.target_lang).map(|glossary_id| glossary_id.to_owned());
+                }
+            }
+        }
+
+        if requests.iter().all(|request| request.source_lang.is_none() || request.glossary_id.is_some()) {
+            return;
+        }
+
+        let glossaries = self.glossaries();
+        for request in requests.iter_mut().filter(|request| request.glossary_id.is_none()) {
+            if let Some(source) = &request.source_lang {
+                request.glossary_id = find_glossary(&glossaries, source, &request.target_lang).map(|glossary| glossary.glossary_id.clone());
+            }
+        }
+    }
+
+    // the glossary picked for a single query with [glossary=<name or id>], it's used even if "Use glossaries" is off
+    // a glossary is always for one language pair, so a request without a source language gets the glossary's
+    pub(crate) fn use_named_glossary(&self, name: &str, request: &mut TranslateRequest) -> Result<(), DeepLError> {
+        let named = self
+            .glossaries()
+            .into_iter()
+            .filter(|glossary| glossary.name.eq_ignore_ascii_case(name) || glossary.glossary_id == name)
+            .collect::<Vec<_>>();
+        if named.is_empty() {
+            return Err(DeepLError::Glossary(format!("No glossary named {}", name)));
+        }
+
+        // several glossaries can share a name, one per language pair
+        let target = glossary_language(&request.target_lang.code());
+        let glossary = match &request.source_lang {
+            Some(source) => find_glossary(&named, source, &request.target_lang),
+            None => named.iter().find(|glossary| glossary.ready && glossary.target_lang.eq_ignore_ascii_case(&target)),
+        };
+
+        let glossary = match glossary {
+            Some(glossary) => glossary,
+            None => {
+                let pair = match &request.source_lang {
+                    Some(source) => format!("{} -> {}", source.code().to_uppercase(), request.target_lang.code().to_uppercase()),
+                    None => request.target_lang.code().to_uppercase(),
+                };
+                return Err(DeepLError::Glossary(format!("Glossary {} isn't for {}", name, pair)));
+            }
+        };
+
+        if request.source_lang.is_none() {
+            request.source_lang = Some(SourceLanguageCode::from_code(&glossary.source_lang.to_uppercase()));
+        }
+        request.glossary_id = Some(glossary.glossary_id.clone());
+        Ok(())
+    }
+
+    // glossaries: | glossary list: [filter]
+    // glossary create: <path to a term file named <source>-<target>.tsv or .csv>
+    // glossary delete: <name or id>
+    pub(crate) fn glossary_command(&self, query: &str) -> Option<Result<Vec<SearchResult>, DeepLError>> {
+        let (command, argument) = query.split_once(':')?;
+        let argument = argument.trim();
+
+        Some(match command.trim().to_lowercase().as_str() {
+            "glossaries" | "glossary list" => Ok(self.list_glossary_results(argument)),
+            "glossary create" => self.create_glossary_result(argument),
+            "glossary delete" => self.delete_glossary_results(argument),
+            _ => return None,
+        })
+    }
+
+    fn list_glossary_results(&self, filter: &str) -> Vec<SearchResult> {
+        let filter = filter.to_lowercase();
+        self.glossaries()
+            .iter()
+            .filter(|glossary| glossary.name.to_lowercase().contains(&filter))
+            .map(|glossary| SearchResult::new(&glossary.name).set_context(&describe(glossary)).set_extra_info(&glossary.glossary_id))
+            .collect()
+    }
+
+    fn create_glossary_result(&self, path: &str) -> Result<Vec<SearchResult>, DeepLError> {
+        let path = path.trim_matches('"');
+        if path.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let (name, source, target, entry_count) = read_glossary_file(Path::new(path))?;
+        let action = Action::CreateGlossary { path: path.to_owned() };
+
+        Ok(vec![SearchResult::new(&format!("Create glossary {}", name))
+            .set_context(&format!("{} -> {}, {} entries from {}", source.to_uppercase(), target.to_uppercase(), entry_count, path))
+            .set_extra_info(&action.encode())])
+    }
+
+    fn delete_glossary_results(&self, name: &str) -> Result<Vec<SearchResult>, DeepLError> {
+        if name.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let results = self
+            .glossaries()
+            .iter()
+            .filter(|glossary| glossary.name.eq_ignore_ascii_case(name) || glossary.glossary_id == name)
+            .map(|glossary| {
+                let action = Action::DeleteGlossary {
+                    glossary_id: glossary.glossary_id.clone(),
+                };
+                SearchResult::new(&format!("Delete glossary {}", glossary.name))
+                    .set_context(&format!("{}, {}", describe(glossary), glossary.glossary_id))
+                    .set_extra_info(&action.encode())
+            })
+            .collect::<Vec<_>>();
+
+        if results.is_empty() {
+            return Err(DeepLError::Glossary(format!("No glossary named {}", name)));
+        }
+
+        Ok(results)
+    }
+
+    pub(crate) fn create_glossary_from_file(&self, path: &Path) -> Result<GlossaryInfo, DeepLError> {
+        let (name, source_lang, target_lang, _) = read_glossary_file(path)?;
+        let (entries, entries_format) = read_term_file(path).map_err(|e| DeepLError::Glossary(format!("Failed to read {}: {}", path.display(), e)))?;
+
+        let glossary = self.backend.create_glossary(&CreateGlossaryRequest {
+            name,
+            source_lang,
+            target_lang,
+            entries,
+            entries_format,
+        })?;
+
+        self.forget_glossaries();
+        Ok(glossary)
+    }
+
+    pub(crate) fn delete_glossary(&self, glossary_id: &str) -> Result<(), DeepLError> {
+        self.backend.delete_glossary(glossary_id)?;
+        self.forget_glossaries();
+
+        // a deleted glossary that came from the glossary directory mustn't be attached to requests anymore
+        if let Ok(mut synced) = self.synced_glossaries.lock() {
+            if synced.forget(|id| id == glossary_id) {
+                self.save_synced_glossaries(&synced);
+            }
+        }
+        Ok(())
+    }
+
+    // upload the term files in the glossary directory whose contents changed since the last sync
+    // glossaries can't be edited, so a changed file gets a new glossary and the old one is deleted
+    pub(crate) fn sync_glossary_dir(&self) {
+        let dir = self
+            .config
+            .get("Glossary directory")
+            .and_then(|entry| entry.as_string())
+            .unwrap_or_default()
+            .trim()
+            .to_owned();
+        let state_path = data_dir().map(|dir| dir.join("glossaries.json"));
+
+        let account = self.account_hash();
+        let mut synced = match &state_path {
+            _ if dir.is_empty() => SyncedGlossaries::default(),
+            Some(state_path) => SyncedGlossaries::load(state_path, &account),
+            // without a data directory, the glossaries synced since the launcher started are all we know about
+            None => match self.synced_glossaries.lock() {
+                Ok(mut current) if current.account == account => std::mem::take(&mut *current),
+                _ => SyncedGlossaries::empty(&account),
+            },
+        };
+
+        if dir.is_empty() {
+            self.set_synced_glossaries(synced);
+            return;
+        }
+
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.logger.warn(&format!("Failed to read the glossary directory {}: {}", dir, e));
+                self.set_synced_glossaries(synced);
+                return;
+            }
+        };
+
+        // glossaries deleted behind our back, e.g. in the DeepL UI, are uploaded again
+        let mut changed = false;
+        if !self.is_offline() {
+            match self.backend.list_glossaries() {
+                Ok(glossaries) => {
+                    let existing = glossaries.iter().map(|glossary| glossary.glossary_id.as_str()).collect::<HashSet<_>>();
+                    changed = synced.forget(|id| !existing.contains(id));
+                }
+                Err(e) => self.logger.warn(&format!("Failed to list glossaries, assuming the synced ones still exist: {}", e)),
+            }
+        }
+
+        let mut seen = HashSet::new();
+
+        for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+            let is_term_file = path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .map(|extension| extension.eq_ignore_ascii_case("tsv") || extension.eq_ignore_ascii_case("csv"))
+                .unwrap_or(false);
+            let (file_name, (source_lang, target_lang)) = match (path.file_name().and_then(|name| name.to_str()), language_pair_from_path(&path)) {
+                (Some(file_name), Some(pair)) if is_term_file => (file_name.to_owned(), pair),
+                _ => continue,
+            };
+            seen.insert(file_name.clone());
+
+            let (entries, entries_format) = match read_term_file(&path) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    self.logger.warn(&format!("Failed to read {}: {}", path.display(), e));
+                    continue;
+                }
+            };
+
+            let hash = content_hash(&format!("{:?}\n{}", entries_format, entries));
+            let previous = synced.files.get(&file_name).cloned();
+            if previous.as_ref().map(|previous| previous.hash == hash).unwrap_or(false) {
+                continue;
+            }
+
+            if self.is_offline() {
+                self.logger.warn(&format!("Offline, {} will be uploaded once DeepL is reachable again", file_name));
+                continue;
+            }
+
+            let request = CreateGlossaryRequest {
+                name: path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default().to_owned(),
+                source_lang,
+                target_lang,
+                entries,
+                entries_format,
+            };
+
+            let glossary = match self.backend.create_glossary(&request) {
+                Ok(glossary) => glossary,
+                Err(e) => {
+                    self.logger.warn(&format!("Failed to create a glossary from {}: {}", file_name, e));
+                    continue;
+                }
+            };
+
+            self.logger
+                .trace(&format!("created glossary {} ({}) from {}", glossary.name, glossary.glossary_id, file_name));
+            if let Some(previous) = previous {
+                self.delete_synced_glossary(&previous.glossary_id);
+            }
+
+            synced.files.insert(
+                file_name,
+                SyncedGlossary {
+                    hash,
+                    glossary_id: glossary.glossary_id,
+                    source_lang: request.source_lang,
+                    target_lang: request.target_lang,
+                },
+            );
+            changed = true;
+        }
+
+        // the files that are gone take their glossaries with them
+        let removed = synced.files.keys().filter(|file_name| !seen.contains(*file_name)).cloned().collect::<Vec<_>>();
+        if !self.is_offline() {
+            for file_name in removed {
+                if let Some(glossary) = synced.files.remove(&file_name) {
+                    self.delete_synced_glossary(&glossary.glossary_id);
+                    changed = true;
+                }
+            }
+        }
+
+        if changed {
+            self.save_synced_glossaries(&synced);
+        }
+
+        self.set_synced_glossaries(synced);
+    }
+
+    fn save_synced_glossaries(&self, synced: &SyncedGlossaries) {
+        if let Some(state_path) = data_dir().map(|dir| dir.join("glossaries.json")) {
+            if let Err(e) = synced.save(&state_path) {
+                self.logger.warn(&format!("Failed to save the synced glossaries: {}", e));
+            }
+        }
+    }
+
+    fn delete_synced_glossary(&self, glossary_id: &str) {
+        if let Err(e) = self.backend.delete_glossary(glossary_id) {
+            self.logger.warn(&format!("Failed to delete the outdated glossary {}: {}", glossary_id, e));
+        }
+    }
+
+    fn set_synced_glossaries(&self, synced: SyncedGlossaries) {
+        if let Ok(mut synced_glossaries) = self.synced_glossaries.lock() {
+            *synced_glossaries = synced;
+        }
+    }
+
+    // which account the synced glossaries belong to, without keeping the api key itself on disk
+    fn account_hash(&self) -> String {
+        let setting = |key: &str| self.config.get(key).and_then(|entry| entry.as_string()).unwrap_or_default().trim().to_owned();
+        let use_free_tier = self.config.get("Use free tier").and_then(|entry| entry.as_bool()).unwrap_or(true);
+        content_hash(&format!("{}\n{}\n{}", setting("DeepL Api Key"), use_free_tier, setting("Custom API base URL")))
+    }
+}
+
+fn describe(glossary: &GlossaryInfo) -> String {
+    let mut description = format!(
+        "{} -> {}, {} entries",
+        glossary.source_lang.to_uppercase(),
+        glossary.target_lang.to_uppercase(),
+        glossary.entry_count
+    );
+    if !glossary.ready {
+        description.push_str(", not ready yet");
+    }
+    description
+}
+
+// the glossary name (the file stem), its language pair and how many terms it has
+fn read_glossary_file(path: &Path) -> Result<(String, String, String, usize), DeepLError> {
+    let (source, target) = language_pair_from_path(path).ok_or_else(|| DeepLError::Glossary(format!("{} isn't named <source>-<target>.tsv or .csv", path.display())))?;
+    let (entries, _) = read_term_file(path).map_err(|e| DeepLError::Glossary(format!("Failed to read {}: {}", path.display(), e)))?;
+    let name = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default().to_owned();
+    Ok((name, source, target, entries.lines().count()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn term_files_are_named_after_their_language_pair() {
+        assert_eq!(language_pair_from_path(Path::new("dir/EN-de.tsv")), Some(("en".to_owned(), "de".to_owned())));
+        assert_eq!(language_pair_from_path(Path::new("en-de.csv")), Some(("en".to_owned(), "de".to_owned())));
+        assert_eq!(language_pair_from_path(Path::new("german.tsv")), None);
+        assert_eq!(language_pair_from_path(Path::new("-de.tsv")), None);
+    }
+
+    #[test]
+    fn term_files_lose_blank_lines_and_carriage_returns() {
+        let path = std::env::temp_dir().join(format!("deepl-terms-{}.csv", std::process::id()));
+        std::fs::write(&path, "hello,hallo\r\n\r\nworld,welt\r\n").unwrap();
+
+        let (entries, format) = read_term_file(&path).unwrap();
+        assert_eq!(entries, "hello,hallo\nworld,welt");
+        assert_eq!(format, EntriesFormat::Csv);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn content_hash_is_stable() {
+        // these end up on disk, so they must not change between builds
+        assert_eq!(content_hash(""), "cbf29ce484222325");
+        assert_eq!(content_hash("hello\thallo"), content_hash("hello\thallo"));
+        assert_ne!(content_hash("hello\thallo"), content_hash("hello\thallo\n"));
+    }
+}
@@ -0,0 +1,728 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::RwLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::{Language, LanguageType, TranslationBackend},
+    error::DeepLError,
+};
+
+// everything the plugin knows about a language without asking the api
+// both language code types are parsed, named and checked against this one table, so the source and target sides can't drift apart
+#[derive(Debug)]
+pub struct LanguageInfo {
+    // the api code, e.g. "PT" or "PT-BR"
+    pub code: &'static str,
+    pub english_name: &'static str,
+    pub native_name: &'static str,
+    // other lowercase names the language is known by, e.g. "jp"
+    pub aliases: &'static [&'static str],
+    pub supports_formality: bool,
+    pub is_source: bool,
+    pub is_target: bool,
+    // regional variants that are only available as targets, e.g. PT-BR and PT-PT for PT
+    pub variants: &'static [LanguageInfo],
+    // the variant to use where the base code is only kept for backward compatibility, e.g. EN-US for EN
+    pub default_variant: Option<&'static str>,
+}
+
+impl LanguageInfo {
+    const fn new(code: &'static str, english_name: &'static str, native_name: &'static str) -> Self {
+        Self {
+            code,
+            english_name,
+            native_name,
+            aliases: &[],
+            supports_formality: false,
+            is_source: true,
+            is_target: true,
+            variants: &[],
+            default_variant: None,
+        }
+    }
+
+    const fn variant(code: &'static str, english_name: &'static str, native_name: &'static str) -> Self {
+        Self {
+            is_source: false,
+            ..Self::new(code, english_name, native_name)
+        }
+    }
+
+    const fn aliases(self, aliases: &'static [&'static str]) -> Self {
+        Self { aliases, ..self }
+    }
+
+    const fn formality(self) -> Self {
+        Self { supports_formality: true, ..self }
+    }
+
+    // the base code stays a valid target for backward compatibility, but deepl would rather get one of the variants
+    const fn variants(self, variants: &'static [LanguageInfo]) -> Self {
+        Self { variants, ..self }
+    }
+
+    const fn default_variant(self, code: &'static str) -> Self {
+        Self {
+            default_variant: Some(code),
+            ..self
+        }
+    }
+
+    fn is(&self, language_type: LanguageType) -> bool {
+        match language_type {
+            LanguageType::Source => self.is_source,
+            LanguageType::Target => self.is_target,
+        }
+    }
+
+    // the code, the english or native name or one of the aliases, case insensitive
+    fn matches(&self, s: &str) -> bool {
+        let s = s.to_lowercase();
+        self.code.eq_ignore_ascii_case(&s) || self.names().any(|name| name == s)
+    }
+
+    // every lowercase name the language goes by, for matching what was typed against
+    fn names(&self) -> impl Iterator<Item = String> + '_ {
+        [self.english_name, self.native_name]
+            .into_iter()
+            .chain(self.aliases.iter().copied())
+            .map(|name| name.to_lowercase())
+    }
+}
+
+#[rustfmt::skip]
+pub static REGISTRY: &[LanguageInfo] = &[
+    LanguageInfo::new("AR", "Arabic", "العربية"),
+    LanguageInfo::new("BG", "Bulgarian", "Български"),
+    LanguageInfo::new("CS", "Czech", "Čeština"),
+    LanguageInfo::new("DA", "Danish", "Dansk"),
+    LanguageInfo::new("DE", "German", "Deutsch").formality(),
+    LanguageInfo::new("EL", "Greek", "Ελληνικά"),
+    LanguageInfo::new("EN", "English", "English").variants(&[
+        LanguageInfo::variant("EN-GB", "English (British)", "English (UK)").aliases(&["en-uk", "british"]),
+        LanguageInfo::variant("EN-US", "English (American)", "English (US)").aliases(&["american"]),
+    ])
+    .default_variant("EN-US"),
+    LanguageInfo::new("ES", "Spanish", "Español").formality().variants(&[
+        LanguageInfo::variant("ES-419", "Spanish (Latin American)", "Español (Latinoamérica)").formality(),
+    ]),
+    LanguageInfo::new("ET", "Estonian", "Eesti"),
+    LanguageInfo::new("FI", "Finnish", "Suomi"),
+    LanguageInfo::new("FR", "French", "Français").formality(),
+    LanguageInfo::new("HU", "Hungarian", "Magyar"),
+    LanguageInfo::new("ID", "Indonesian", "Bahasa Indonesia"),
+    LanguageInfo::new("IT", "Italian", "Italiano").formality(),
+    LanguageInfo::new("JA", "Japanese", "日本語").aliases(&["jp"]).formality(),
+    LanguageInfo::new("KO", "Korean", "한국어"),
+    LanguageInfo::new("LT", "Lithuanian", "Lietuvių"),
+    LanguageInfo::new("LV", "Latvian", "Latviešu"),
+    LanguageInfo::new("NB", "Norwegian (Bokmål)", "Norsk bokmål").aliases(&["norwegian"]),
+    LanguageInfo::new("NL", "Dutch", "Nederlands").formality(),
+    LanguageInfo::new("PL", "Polish", "Polski").formality(),
+    LanguageInfo::new("PT", "Portuguese", "Português").variants(&[
+        LanguageInfo::variant("PT-BR", "Portuguese (Brazilian)", "Português (Brasil)").aliases(&["brazilian"]).formality(),
+        LanguageInfo::variant("PT-PT", "Portuguese (European)", "Português (Portugal)").formality(),
+    ])
+    .default_variant("PT-BR"),
+    LanguageInfo::new("RO", "Romanian", "Română"),
+    LanguageInfo::new("RU", "Russian", "Русский").formality(),
+    LanguageInfo::new("SK", "Slovak", "Slovenčina"),
+    LanguageInfo::new("SL", "Slovenian", "Slovenščina"),
+    LanguageInfo::new("SV", "Swedish", "Svenska"),
+    LanguageInfo::new("TR", "Turkish", "Türkçe"),
+    LanguageInfo::new("UK", "Ukrainian", "Українська"),
+    LanguageInfo::new("ZH", "Chinese", "中文").variants(&[
+        LanguageInfo::variant("ZH-HANS", "Chinese (simplified)", "简体中文").aliases(&["zh-cn", "simplified chinese"]),
+        LanguageInfo::variant("ZH-HANT", "Chinese (traditional)", "繁體中文").aliases(&["zh-tw", "traditional chinese"]),
+    ])
+    .default_variant("ZH-HANS"),
+];
+
+// every language and variant in the registry that can be used as `language_type`
+pub fn registry(language_type: LanguageType) -> impl Iterator<Item = &'static LanguageInfo> {
+    REGISTRY
+        .iter()
+        .flat_map(|language| std::iter::once(language).chain(language.variants.iter()))
+        .filter(move |language| language.is(language_type))
+}
+
+// anything shorter is only matched exactly
+const MIN_FUZZY_LENGTH: usize = 3;
+// candidates offered for an ambiguous or misspelled name
+const MAX_SUGGESTIONS: usize = 5;
+
+pub enum LanguageMatch {
+    Found(String),
+    // several languages fit equally well, as (code, english name)
+    Candidates(Vec<(String, String)>),
+    // none fit, but these came close, as (code, english name)
+    Suggestions(Vec<(String, String)>),
+    NotFound,
+}
+
+// like find_code, but also forgiving about what was typed:
+// an unambiguous prefix ("germ"), a small typo ("portugese"), and otherwise the languages that came close
+pub fn match_language(language_type: LanguageType, s: &str) -> LanguageMatch {
+    let s = s.trim().to_lowercase();
+    if let Some(code) = find_code(language_type, &s) {
+        return LanguageMatch::Found(code);
+    }
+
+    // codes are too short to guess from, "xx" shouldn't turn into "et"
+    if s.chars().count() < MIN_FUZZY_LENGTH {
+        return LanguageMatch::NotFound;
+    }
+
+    let names = names(language_type);
+
+    let prefixed = names.iter().filter(|(name, _)| name.starts_with(&s)).map(|(_, code)| code.clone()).collect::<Vec<_>>();
+    if !prefixed.is_empty() {
+        return pick(language_type, prefixed);
+    }
+
+    // longer names get to be a little more wrong
+    let max_distance = if s.chars().count() <= 6 { 1 } else { 2 };
+    let mut distances = names.iter().map(|(name, code)| (edit_distance(&s, name), code.clone())).collect::<Vec<_>>();
+    distances.sort();
+
+    let closest = distances.first().map(|(distance, _)| *distance).unwrap_or(usize::MAX);
+    if closest <= max_distance {
+        let codes = distances.iter().filter(|(distance, _)| *distance == closest).map(|(_, code)| code.clone()).collect();
+        return pick(language_type, codes);
+    }
+
+    let suggestions = distances
+        .into_iter()
+        .filter(|(distance, _)| *distance <= max_distance + 1)
+        .map(|(_, code)| code)
+        .collect::<Vec<_>>();
+    if suggestions.is_empty() {
+        return LanguageMatch::NotFound;
+    }
+    LanguageMatch::Suggestions(candidates(language_type, suggestions))
+}
+
+// one language, or a base language and its variants ("español" also starts "español (latinoamérica)"), is a match
+// anything else is for the user to choose from
+fn pick(language_type: LanguageType, codes: Vec<String>) -> LanguageMatch {
+    let codes = dedup(codes);
+    if codes.len() == 1 {
+        return LanguageMatch::Found(codes[0].clone());
+    }
+
+    let base = codes
+        .iter()
+        .find(|code| !code.contains('-') && codes.iter().all(|other| other.split('-').next() == Some(code.as_str())));
+    match base {
+        Some(base) => LanguageMatch::Found(base.clone()),
+        None => LanguageMatch::Candidates(candidates(language_type, codes)),
+    }
+}
+
+fn candidates(language_type: LanguageType, codes: Vec<String>) -> Vec<(String, String)> {
+    dedup(codes)
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|code| (code.clone(), name(language_type, &code)))
+        .collect()
+}
+
+// keeps the first of every code, so the best matches stay in front
+fn dedup(codes: Vec<String>) -> Vec<String> {
+    let mut unique: Vec<String> = vec![];
+    for code in codes {
+        if !unique.contains(&code) {
+            unique.push(code);
+        }
+    }
+    unique
+}
+
+// every (lowercase name, code) pair of the registry and the fetched list
+fn names(language_type: LanguageType) -> Vec<(String, String)> {
+    let mut names = registry(language_type)
+        .flat_map(|language| language.names().map(move |name| (name, language.code.to_owned())))
+        .collect::<Vec<_>>();
+
+    if let Ok(languages) = LANGUAGES.read() {
+        if let Some(list) = languages.as_ref() {
+            names.extend(
+                list.languages(language_type)
+                    .iter()
+                    .map(|language| (language.name.to_lowercase(), language.language.to_uppercase())),
+            );
+        }
+    }
+
+    names
+}
+
+// levenshtein distance over chars, so native names aren't measured in bytes
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + if a == *b { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+// the api code for a code, name or alias, e.g. "german" or "deutsch" -> "DE"
+// the user's aliases come first, then the registry, then the list fetched from the api for anything newer than the registry
+pub fn find_code(language_type: LanguageType, s: &str) -> Option<String> {
+    let s = s.trim();
+    if let Some(code) = find_user_alias(language_type, s) {
+        return Some(code);
+    }
+
+    find_builtin_code(language_type, s)
+}
+
+fn find_builtin_code(language_type: LanguageType, s: &str) -> Option<String> {
+    if let Some(language) = registry(language_type).find(|language| language.matches(s)) {
+        return Some(language.code.to_owned());
+    }
+
+    find(language_type, s).map(|language| language.language.to_uppercase())
+}
+
+// the english name for a code, from the fetched list if there is one since it knows the newest names
+pub fn name(language_type: LanguageType, code: &str) -> String {
+    if let Some(language) = find(language_type, code) {
+        return language.name;
+    }
+
+    registry(language_type)
+        .find(|language| language.code.eq_ignore_ascii_case(code))
+        .map(|language| language.english_name.to_owned())
+        .unwrap_or_else(|| code.to_owned())
+}
+
+// a target deepl is happy with for a base language, ES stays ES but EN becomes EN-US
+pub fn concrete_target(code: &str) -> String {
+    REGISTRY
+        .iter()
+        .find(|language| language.code.eq_ignore_ascii_case(code))
+        .and_then(|language| language.default_variant)
+        .unwrap_or(code)
+        .to_owned()
+}
+
+pub fn supports_formality(code: &str) -> bool {
+    if let Some(language) = find(LanguageType::Target, code) {
+        return language.supports_formality;
+    }
+
+    registry(LanguageType::Target).any(|language| language.supports_formality && language.code.eq_ignore_ascii_case(code))
+}
+
+// lowercase alias -> api code, from the "Language aliases" config entry
+static USER_ALIASES: RwLock<Option<HashMap<String, String>>> = RwLock::new(None);
+
+fn find_user_alias(language_type: LanguageType, s: &str) -> Option<String> {
+    resolve_alias(USER_ALIASES.read().ok()?.as_ref()?, language_type, s)
+}
+
+// an alias for a variant also works where only the base language makes sense, e.g. us -> EN-US gives EN as a source
+fn resolve_alias(aliases: &HashMap<String, String>, language_type: LanguageType, s: &str) -> Option<String> {
+    let code = aliases.get(&s.to_lowercase())?;
+
+    let base = code.split('-').next().unwrap_or_default();
+    [code.as_str(), base].into_iter().find(|code| is_known(language_type, code)).map(|code| code.to_owned())
+}
+
+fn is_known(language_type: LanguageType, code: &str) -> bool {
+    registry(language_type).any(|language| language.code.eq_ignore_ascii_case(code)) || find(language_type, code).is_some()
+}
+
+// replaces the current aliases with the ones in `aliases`, see parse_user_aliases
+pub fn set_user_aliases(aliases: &str) -> Vec<String> {
+    let (parsed, warnings) = parse_user_aliases(aliases);
+    if let Ok(mut user_aliases) = USER_ALIASES.write() {
+        *user_aliases = Some(parsed);
+    }
+
+    warnings
+}
+
+// parses aliases like "br=pt-br, us=en-us, cn=zh" (commas or new lines between them) into lowercase alias -> api code
+// gives back a warning for everything that was skipped or shadows a built in name, for the caller to log
+fn parse_user_aliases(aliases: &str) -> (HashMap<String, String>, Vec<String>) {
+    let mut parsed: HashMap<String, String> = HashMap::new();
+    let mut warnings = vec![];
+
+    for entry in aliases.split([',', '\n']).map(|entry| entry.trim()).filter(|entry| !entry.is_empty()) {
+        let (alias, language) = match entry.split_once('=') {
+            Some((alias, language)) if !alias.trim().is_empty() => (alias.trim().to_lowercase(), language.trim()),
+            _ => {
+                warnings.push(format!("Ignoring language alias \"{}\", use <alias>=<language>", entry));
+                continue;
+            }
+        };
+
+        // the alias can point at anything the built in table understands, codes, names or its own aliases
+        let code = match find_builtin_code(LanguageType::Target, language).or_else(|| find_builtin_code(LanguageType::Source, language)) {
+            Some(code) => code,
+            None => {
+                warnings.push(format!("Ignoring language alias \"{}\", {} isn't a known language", alias, language));
+                continue;
+            }
+        };
+
+        for language_type in [LanguageType::Source, LanguageType::Target] {
+            if let Some(builtin) = find_builtin_code(language_type, &alias) {
+                if builtin != code && builtin.split('-').next() != Some(code.as_str()) {
+                    warnings.push(format!("Language alias \"{}\" for {} shadows the built in \"{}\" for {}", alias, code, alias, builtin));
+                    break;
+                }
+            }
+        }
+
+        if let Some(previous) = parsed.insert(alias.clone(), code.clone()) {
+            if previous != code {
+                warnings.push(format!("Language alias \"{}\" is defined twice, using {} instead of {}", alias, code, previous));
+            }
+        }
+    }
+
+    (parsed, warnings)
+}
+
+// the languages deepl supports right now, as reported by /v2/languages
+// the registry only knows the languages that existed when it was written, so it is the fallback while this is empty
+// it's global because the language codes' parsing and Display have no plugin to ask
+static LANGUAGES: RwLock<Option<LanguageList>> = RwLock::new(None);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LanguageList {
+    // seconds since the unix epoch
+    fetched: u64,
+    source: Vec<Language>,
+    target: Vec<Language>,
+}
+
+impl LanguageList {
+    pub fn fetch(backend: &dyn TranslationBackend) -> Result<Self, DeepLError> {
+        Ok(Self {
+            fetched: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            source: backend.languages(LanguageType::Source)?,
+            target: backend.languages(LanguageType::Target)?,
+        })
+    }
+
+    // a broken file is treated like a missing one, it'll just be fetched again
+    pub fn load(path: &Path) -> Option<Self> {
+        std::fs::read_to_string(path).ok().and_then(|contents| serde_json::from_str(&contents).ok())
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let temp_path = path.with_extension("tmp");
+        std::fs::write(&temp_path, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&temp_path, path)
+    }
+
+    // a ttl of zero means the list never expires
+    pub fn is_expired(&self, ttl: Duration) -> bool {
+        let age = SystemTime::now().duration_since(UNIX_EPOCH + Duration::from_secs(self.fetched)).unwrap_or_default();
+        !ttl.is_zero() && age > ttl
+    }
+
+    fn languages(&self, language_type: LanguageType) -> &[Language] {
+        match language_type {
+            LanguageType::Source => &self.source,
+            LanguageType::Target => &self.target,
+        }
+    }
+}
+
+pub fn set(list: LanguageList) {
+    if let Ok(mut languages) = LANGUAGES.write() {
+        *languages = Some(list);
+    }
+}
+
+// the language with this code or english name, both case insensitive, e.g. "zh-hant" or "chinese (traditional)"
+pub fn find(language_type: LanguageType, s: &str) -> Option<Language> {
+    let languages = LANGUAGES.read().ok()?;
+    languages
+        .as_ref()?
+        .languages(language_type)
+        .iter()
+        .find(|language| language.language.eq_ignore_ascii_case(s) || language.name.eq_ignore_ascii_case(s))
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SourceLanguageCode, TargetLanguageCode};
+
+    fn list(fetched: u64) -> LanguageList {
+        let language = |language: &str, name: &str| Language {
+            language: language.to_owned(),
+            name: name.to_owned(),
+            supports_formality: false,
+        };
+
+        LanguageList {
+            fetched,
+            source: vec![language("DE", "German")],
+            target: vec![language("DE", "German"), language("ZH-HANT", "Chinese (traditional)")],
+        }
+    }
+
+    fn now() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+    }
+
+    #[test]
+    fn lists_expire_after_the_ttl() {
+        let ttl = Duration::from_secs(60);
+        assert!(!list(now()).is_expired(ttl));
+        assert!(list(now() - 120).is_expired(ttl));
+        // a ttl of zero keeps a list forever
+        assert!(!list(0).is_expired(Duration::ZERO));
+    }
+
+    #[test]
+    fn lists_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!("deepl-languages-{}.json", std::process::id()));
+        list(1234).save(&path).unwrap();
+
+        let loaded = LanguageList::load(&path).unwrap();
+        assert_eq!(loaded.fetched, 1234);
+        assert_eq!(loaded.languages(LanguageType::Target)[1].language, "ZH-HANT");
+
+        // a broken file is the same as none at all
+        std::fs::write(&path, "[not a list").unwrap();
+        assert!(LanguageList::load(&path).is_none());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    // every named variant, the match makes adding a variant without listing it here a compile error
+    fn named_sources() -> Vec<SourceLanguageCode> {
+        use SourceLanguageCode::*;
+
+        let named = vec![
+            AR, BG, CS, DA, DE, EL, EN, ES, ET, FI, FR, HU, ID, IT, JA, KO, LT, LV, NB, NL, PL, PT, RO, RU, SK, SL, SV, TR, UK, ZH,
+        ];
+        for code in &named {
+            match code {
+                AR | BG | CS | DA | DE | EL | EN | ES | ET | FI | FR | HU | ID | IT | JA | KO | LT | LV | NB | NL | PL | PT | RO | RU | SK | SL | SV | TR | UK | ZH => {}
+                Other(_) => unreachable!(),
+            }
+        }
+        named
+    }
+
+    fn named_targets() -> Vec<TargetLanguageCode> {
+        use TargetLanguageCode::*;
+
+        let named = vec![
+            AR, BG, CS, DA, DE, EL, EN, EnGb, EnUs, ES, ET, FI, FR, HU, ID, IT, JA, KO, LT, LV, NB, NL, PL, PT, PtBr, PtPt, RO, RU, SK, SL, SV, TR, UK, ZH,
+        ];
+        for code in &named {
+            match code {
+                AR | BG | CS | DA | DE | EL | EN | EnGb | EnUs | ES | ET | FI | FR | HU | ID | IT | JA | KO | LT | LV | NB | NL | PL | PT | PtBr | PtPt | RO | RU | SK | SL
+                | SV | TR | UK | ZH => {}
+                Other(_) => unreachable!(),
+            }
+        }
+        named
+    }
+
+    // the code, both names and every alias of every language parse back to its code, and display as its english name
+    #[test]
+    fn source_codes_round_trip() {
+        for language in registry(LanguageType::Source) {
+            for name in [language.code, language.english_name, language.native_name]
+                .into_iter()
+                .chain(language.aliases.iter().copied())
+            {
+                let parsed = name.parse::<SourceLanguageCode>().unwrap_or_else(|e| panic!("{} doesn't parse: {}", name, e));
+                assert_eq!(parsed.code(), language.code, "{} parses to the wrong language", name);
+                assert_eq!(parsed.to_string(), language.english_name);
+            }
+        }
+    }
+
+    #[test]
+    fn target_codes_round_trip() {
+        for language in registry(LanguageType::Target) {
+            for name in [language.code, language.english_name, language.native_name]
+                .into_iter()
+                .chain(language.aliases.iter().copied())
+            {
+                let parsed = name.parse::<TargetLanguageCode>().unwrap_or_else(|e| panic!("{} doesn't parse: {}", name, e));
+                assert_eq!(parsed.code(), language.code, "{} parses to the wrong language", name);
+                assert_eq!(parsed.to_string(), language.english_name);
+                assert_eq!(parsed.supports_formality(), language.supports_formality, "{}", language.code);
+            }
+        }
+    }
+
+    // the named variants and the registry list the same languages, apart from the targets that are deliberately left to Other
+    #[test]
+    fn named_sources_match_the_registry() {
+        let registered = registry(LanguageType::Source).map(|language| language.code).collect::<Vec<_>>();
+        let named = named_sources();
+
+        for code in &named {
+            assert!(registered.contains(&code.code().as_str()), "{:?} isn't a source language in the registry", code);
+        }
+        for code in &registered {
+            assert!(named.contains(&SourceLanguageCode::from_code(code)), "{} has no named variant", code);
+        }
+    }
+
+    #[test]
+    fn named_targets_match_the_registry() {
+        let registered = registry(LanguageType::Target).map(|language| language.code).collect::<Vec<_>>();
+        let named = named_targets();
+
+        for code in &named {
+            assert!(registered.contains(&code.code().as_str()), "{:?} isn't a target language in the registry", code);
+        }
+
+        let unnamed = registered
+            .into_iter()
+            .filter(|code| !named.contains(&TargetLanguageCode::from_code(code)))
+            .collect::<Vec<_>>();
+        assert_eq!(unnamed, ["ES-419", "ZH-HANS", "ZH-HANT"]);
+    }
+
+    // registry targets that aren't sources are the variants of a source language
+    #[test]
+    fn variants_are_targets_only() {
+        for language in REGISTRY {
+            assert!(language.is_source && language.is_target, "{}", language.code);
+            for variant in language.variants {
+                assert!(!variant.is_source && variant.is_target, "{}", variant.code);
+                assert!(variant.code.starts_with(&format!("{}-", language.code)), "{}", variant.code);
+            }
+        }
+    }
+
+    #[test]
+    fn base_languages_that_deepl_wants_a_variant_for_get_one() {
+        for language in REGISTRY {
+            if let Some(default) = language.default_variant {
+                assert!(language.variants.iter().any(|variant| variant.code == default), "{}", language.code);
+            }
+        }
+
+        assert_eq!(concrete_target("EN"), "EN-US");
+        assert_eq!(concrete_target("pt"), "PT-BR");
+        assert_eq!(concrete_target("ES"), "ES");
+        assert_eq!(concrete_target("DE"), "DE");
+        assert_eq!(concrete_target("EN-GB"), "EN-GB");
+    }
+
+    fn found(s: &str) -> Option<String> {
+        match match_language(LanguageType::Target, s) {
+            LanguageMatch::Found(code) => Some(code),
+            _ => None,
+        }
+    }
+
+    fn candidates(s: &str) -> Vec<String> {
+        match match_language(LanguageType::Target, s) {
+            LanguageMatch::Candidates(candidates) => candidates.into_iter().map(|(code, _)| code).collect(),
+            _ => vec![],
+        }
+    }
+
+    #[test]
+    fn languages_match_by_prefix_typo_and_native_name() {
+        assert_eq!(found("germ").as_deref(), Some("DE"));
+        assert_eq!(found("portugese").as_deref(), Some("PT"));
+        assert_eq!(found("deutsch").as_deref(), Some("DE"));
+        assert_eq!(found("Español").as_deref(), Some("ES"));
+        assert_eq!(found("日本語").as_deref(), Some("JA"));
+    }
+
+    fn suggestions(s: &str) -> Vec<String> {
+        match match_language(LanguageType::Target, s) {
+            LanguageMatch::Suggestions(suggestions) => suggestions.into_iter().map(|(code, _)| code).collect(),
+            _ => vec![],
+        }
+    }
+
+    #[test]
+    fn ambiguous_prefixes_offer_every_candidate() {
+        assert_eq!(candidates("slo"), ["SK", "SL"]);
+    }
+
+    // near misses aren't candidates, "check" may well not be about czech at all
+    #[test]
+    fn near_misses_are_only_suggestions() {
+        assert!(candidates("check").is_empty());
+        assert!(suggestions("check").contains(&"CS".to_owned()));
+        assert!(suggestions("tip").contains(&"JA".to_owned()));
+    }
+
+    #[test]
+    fn short_inputs_are_only_matched_exactly() {
+        assert!(matches!(match_language(LanguageType::Target, "xx"), LanguageMatch::NotFound));
+        // "ge" is the start of "german", but too short to be read as it
+        assert!(matches!(match_language(LanguageType::Target, "ge"), LanguageMatch::NotFound));
+    }
+
+    #[test]
+    fn edit_distance_counts_chars() {
+        assert_eq!(edit_distance("portugese", "portuguese"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("español", "espanol"), 1);
+    }
+
+    #[test]
+    fn aliases_work_as_targets_and_as_base_languages() {
+        let (aliases, warnings) = parse_user_aliases("br=pt-br, us = English (American)\nCN=zh");
+        assert!(warnings.is_empty(), "{:?}", warnings);
+
+        assert_eq!(resolve_alias(&aliases, LanguageType::Target, "br").as_deref(), Some("PT-BR"));
+        // PT-BR is a target only, as a source it's PT
+        assert_eq!(resolve_alias(&aliases, LanguageType::Source, "BR").as_deref(), Some("PT"));
+        assert_eq!(resolve_alias(&aliases, LanguageType::Target, "us").as_deref(), Some("EN-US"));
+        assert_eq!(resolve_alias(&aliases, LanguageType::Source, "cn").as_deref(), Some("ZH"));
+        assert_eq!(resolve_alias(&aliases, LanguageType::Target, "de"), None);
+    }
+
+    #[test]
+    fn alias_problems_are_warned_about() {
+        let (aliases, warnings) = parse_user_aliases("de=fr, x=pt, x=es, oops, =de, y=klingon");
+        assert_eq!(
+            warnings,
+            [
+                "Language alias \"de\" for FR shadows the built in \"de\" for DE",
+                "Language alias \"x\" is defined twice, using ES instead of PT",
+                "Ignoring language alias \"oops\", use <alias>=<language>",
+                "Ignoring language alias \"=de\", use <alias>=<language>",
+                "Ignoring language alias \"y\", klingon isn't a known language",
+            ]
+        );
+
+        // a shadowing alias still wins, it's only pointed out
+        assert_eq!(resolve_alias(&aliases, LanguageType::Target, "de").as_deref(), Some("FR"));
+        assert_eq!(resolve_alias(&aliases, LanguageType::Target, "x").as_deref(), Some("ES"));
+        assert_eq!(aliases.len(), 2);
+    }
+}
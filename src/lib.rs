@@ -7,9 +7,63 @@ use abi_stable::{
 };
 use quick_search_lib::{ColoredChar, Log, PluginId, SearchLib, SearchLib_Ref, SearchResult, Searchable, Searchable_TO};
 use serde::{Deserialize, Serialize};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc,
+};
+
+mod back_translation;
+mod backend;
+mod budget;
+mod cache;
+mod error;
+mod glossary;
+mod languages;
+mod parser;
+mod usage;
+
+use backend::{DeepLBackend, Formality, LanguageType, TranslateRequest, TranslateResponse, TranslationBackend};
+use budget::CharacterBudget;
+use cache::{DiskCache, TranslationCache};
+use error::DeepLError;
+use glossary::{GlossaryInfo, SyncedGlossaries};
+use parser::{GlossaryOption, Modifier, Target};
 
 static NAME: &str = "DeepL-Translate";
 
+const DEFAULT_CACHE_CAPACITY: usize = 256;
+const DEFAULT_CACHE_TTL: u64 = 60 * 60 * 24;
+const DEFAULT_PERSISTENT_CACHE_SIZE: u64 = 2000;
+const DEFAULT_CONNECT_TIMEOUT_MS: u64 = 5000;
+const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 10000;
+const DEFAULT_MAX_RETRIES: u64 = 2;
+const DEFAULT_DEBOUNCE_MS: u64 = 250;
+const DEFAULT_USAGE_WARNING_THRESHOLD: u64 = 80;
+// no budget
+const DEFAULT_MONTHLY_CHARACTER_BUDGET: u64 = 0;
+const DEFAULT_LANGUAGE_LIST_TTL: u64 = 60 * 60 * 24 * 7;
+const OFFLINE_PROBE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+// results normally copy their extra info to the clipboard, results that do something else carry an action instead
+// the prefix keeps them apart from translations, which can be any text
+const ACTION_PREFIX: &str = "deepl-plugin-action:";
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Action {
+    CreateGlossary { path: String },
+    DeleteGlossary { glossary_id: String },
+}
+
+impl Action {
+    fn encode(&self) -> String {
+        format!("{}{}", ACTION_PREFIX, serde_json::to_string(self).unwrap_or_default())
+    }
+
+    fn decode(extra_info: &str) -> Option<Self> {
+        serde_json::from_str(extra_info.strip_prefix(ACTION_PREFIX)?).ok()
+    }
+}
+
 #[export_root_module]
 pub fn get_library() -> SearchLib_Ref {
     SearchLib { get_searchable }.leak_into_prefix()
@@ -23,170 +77,528 @@ fn get_searchable(id: PluginId, logger: quick_search_lib::ScopedLogger) -> Searc
 
 struct DeepL {
     id: PluginId,
-    client: reqwest::blocking::Client,
+    backend: Arc<dyn TranslationBackend>,
+    cache: std::sync::Mutex<TranslationCache>,
+    // set after a connection error, and cleared again by a background probe once the api can be reached
+    offline: Arc<AtomicBool>,
+    // bumped by every search, so a search can tell that it has been superseded by a newer one
+    generation: AtomicU64,
+    // the account's glossaries, None until they are first needed
+    glossaries: std::sync::Mutex<Option<Vec<GlossaryInfo>>>,
+    // glossaries created from the glossary directory
+    synced_glossaries: std::sync::Mutex<SyncedGlossaries>,
+    // characters billed this month, checked against "Monthly character budget" before every request
+    budget: std::sync::Mutex<CharacterBudget>,
     config: quick_search_lib::Config,
-    logger: quick_search_lib::ScopedLogger,
+    logger: Logger,
+}
+
+// the launcher's logger, or None where there's no launcher to hand one over, like in the tests
+// shared so background work can log too
+#[derive(Clone)]
+struct Logger(Option<Arc<quick_search_lib::ScopedLogger>>);
+
+impl Logger {
+    fn trace(&self, message: &str) {
+        if let Some(logger) = &self.0 {
+            logger.trace(message);
+        }
+    }
+
+    fn warn(&self, message: &str) {
+        if let Some(logger) = &self.0 {
+            logger.warn(message);
+        }
+    }
+
+    fn error(&self, message: &str) {
+        if let Some(logger) = &self.0 {
+            logger.error(message);
+        }
+    }
 }
 
 impl DeepL {
     fn new(id: PluginId, logger: quick_search_lib::ScopedLogger) -> Self {
+        let config = default_config();
+        Self::with_backend(id, Logger(Some(Arc::new(logger))), Arc::new(DeepLBackend::new(&config)))
+    }
+
+    // lets the query handling below run against any backend, not just the DeepL http api
+    fn with_backend(id: PluginId, logger: Logger, backend: Arc<dyn TranslationBackend>) -> Self {
         Self {
             id,
             logger,
-            client: reqwest::blocking::Client::new(),
+            backend,
+            cache: std::sync::Mutex::new(TranslationCache::new(DEFAULT_CACHE_CAPACITY, std::time::Duration::from_secs(DEFAULT_CACHE_TTL))),
+            offline: Arc::new(AtomicBool::new(false)),
+            generation: AtomicU64::new(0),
+            glossaries: std::sync::Mutex::new(None),
+            synced_glossaries: std::sync::Mutex::new(SyncedGlossaries::default()),
+            budget: std::sync::Mutex::new(CharacterBudget::new()),
             config: default_config(),
         }
     }
-}
 
-impl Searchable for DeepL {
-    fn search(&self, query: RString) -> RVec<SearchResult> {
-        let mut res: Vec<SearchResult> = vec![];
+    fn config_number(&self, key: &str, default: u64) -> u64 {
+        config_number(&self.config, key, default).unwrap_or_else(|value| {
+            self.logger.warn(&format!("Invalid number for \"{}\": {}", key, value));
+            default
+        })
+    }
 
-        // let return_error_messages = self.config.get("Return Error messages").and_then(|entry| entry.as_bool()).unwrap_or(false);
-        let api_key = self.config.get("DeepL Api Key").and_then(|entry| entry.as_string()).unwrap_or_default();
+    // a formality the api doesn't know would fail every query, so it's only warned about and left out
+    fn default_formality(&self) -> Option<Formality> {
+        let formality = self
+            .config
+            .get("Default formality")
+            .and_then(|entry| entry.as_string())
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+        if formality.is_empty() {
+            return None;
+        }
 
-        if api_key.is_empty() {
-            // if return_error_messages {
-            //     res.push(SearchResult::new("No API key").set_context("No DeepL API key was provided"));
-            // }
-            self.logger.error("No API key was provided");
-            return res.into();
+        let parsed = Formality::guess_from_str(&formality);
+        if parsed.is_none() {
+            self.logger.warn(&format!("{}, ignoring \"Default formality\"", DeepLError::InvalidFormality(formality)));
         }
-        // attempt to parse the query into one of:
-        // <target_language_code>: <query>
-        // <source_language_code> -> <target_language_code>: <query>
-        // we will trim spaces so:
-        // <source_language_code>-><target_language_code>:<query> is also valid
-
-        // first, lets split on the first colon, if we get less than 2 parts, return the empty results early
-        let mut parts = query.split(':');
-        let query_codes = match parts.next() {
-            Some(part) => part.trim().to_owned(),
-            None => return res.into(),
-        };
+        parsed
+    }
+
+    // the bool is true when the translation came out of the cache because the plugin is offline
+    fn translate_cached(&self, request: &TranslateRequest) -> Result<(TranslateResponse, bool), DeepLError> {
+        let key = cache::cache_key(request);
+        let offline = self.is_offline();
 
-        if query_codes.is_empty() {
-            return res.into();
+        if let Some(response) = self.cached(key.as_deref(), offline) {
+            self.logger.trace("Serving translation from cache");
+            return Ok((response, offline));
         }
 
-        // collect the rest of the parts into a string joined by colons (to fix the split)
-        let rest = parts.map(|s| s.to_owned()).collect::<Vec<String>>().join(":");
-        let rest = rest.trim().to_owned();
+        if offline {
+            return Err(DeepLError::Offline);
+        }
+
+        let characters = request.text.iter().map(|text| text.chars().count() as u64).sum::<u64>();
+        self.reserve_budget(characters)?;
+
+        let response = match self.backend.translate(request) {
+            Ok(response) => response,
+            Err(e @ DeepLError::Connection(_)) => {
+                self.release_budget(characters);
+                self.go_offline();
+
+                // the fresh lookup above missed, but an expired translation is still better than nothing
+                return match self.cached(key.as_deref(), true) {
+                    Some(response) => Ok((response, true)),
+                    None => Err(e),
+                };
+            }
+            Err(e) => {
+                self.release_budget(characters);
+                return Err(e);
+            }
+        };
 
-        if rest.is_empty() {
-            // if return_error_messages {
-            //     res.push(SearchResult::new("No query").set_context("No query was provided"));
-            // }
-            self.logger.trace("No query was provided");
-            return res.into();
+        // older api versions don't report billed characters, the text length is what they bill anyway
+        let billed = response
+            .translations
+            .iter()
+            .map(|translation| translation.billed_characters)
+            .sum::<Option<u64>>()
+            .unwrap_or(characters);
+        if let Ok(mut budget) = self.budget.lock() {
+            if let Err(e) = budget.record(characters, billed) {
+                self.logger.warn(&format!("Failed to save the character budget: {}", e));
+            }
         }
 
-        // now we can split the first part on the arrow, we should only get 1 or 2 parts, if we get 0 or more than 2, return the empty results early
-        let mut parts = query_codes.split("->");
-        let query = match (parts.next(), parts.next(), parts.next()) {
-            (_, _, Some(_)) => {
-                // if return_error_messages {
-                //     res.push(SearchResult::new("Invalid query").set_context("Too many arrows"));
-                // }
-                self.logger.warn("Too many arrows in the query");
-                return res.into();
+        if let (Some(key), Ok(mut cache)) = (key, self.cache.lock()) {
+            if let Err(e) = cache.insert(key, response.clone()) {
+                self.logger.warn(&format!("Failed to write to the persistent cache: {}", e));
             }
-            (Some(source), Some(target), None) => {
-                let source = source.trim().to_lowercase();
-                let target = target.trim().to_lowercase();
-
-                let source = match SourceLanguageCode::guess_from_str(&source) {
-                    Some(code) => code,
-                    None => {
-                        // if return_error_messages {
-                        //     res.push(SearchResult::new("Invalid query").set_context("Invalid source language code"));
-                        // }
-                        self.logger.warn("Invalid source language code");
-                        return res.into();
-                    }
-                };
+        }
 
-                let target = match TargetLanguageCode::guess_from_str(&target) {
-                    Some(code) => code,
-                    None => {
-                        // if return_error_messages {
-                        //     res.push(SearchResult::new("Invalid query").set_context("Invalid target language code"));
-                        // }
-                        self.logger.warn("Invalid target language code");
-                        return res.into();
-                    }
-                };
+        Ok((response, false))
+    }
+
+    fn reserve_budget(&self, characters: u64) -> Result<(), DeepLError> {
+        let limit = self.config_number("Monthly character budget", DEFAULT_MONTHLY_CHARACTER_BUDGET);
+        match self.budget.lock() {
+            Ok(mut budget) => budget.reserve(characters, limit).map_err(|used| DeepLError::BudgetExceeded(used, limit)),
+            Err(_) => Ok(()),
+        }
+    }
+
+    fn release_budget(&self, characters: u64) {
+        if let Ok(mut budget) = self.budget.lock() {
+            budget.release(characters);
+        }
+    }
 
-                TranslateRequest {
-                    text: vec![rest.clone()],
-                    target_lang: target,
-                    source_lang: Some(source),
+    fn load_budget(&self) {
+        let budget = match data_dir() {
+            Some(dir) => {
+                let mut budget = CharacterBudget::at(dir.join("budget.json"));
+                if let Err(e) = budget.refresh() {
+                    self.logger
+                        .warn(&format!("Failed to load the character budget, it won't be saved until the file is fixed: {}", e));
                 }
+                budget
             }
-            (Some(target), None, None) => {
-                let target = target.trim().to_lowercase();
-
-                let target = match TargetLanguageCode::guess_from_str(&target) {
-                    Some(code) => code,
-                    None => {
-                        // if return_error_messages {
-                        //     res.push(SearchResult::new("Invalid query").set_context("Invalid target language code"));
-                        // }
-                        self.logger.warn("Invalid target language code");
-                        return res.into();
+            None => {
+                self.logger.warn("No data directory found, billed characters won't be counted across restarts");
+                CharacterBudget::new()
+            }
+        };
+
+        if let Ok(mut current) = self.budget.lock() {
+            *current = budget;
+        }
+    }
+
+    // the list on disk is used straight away, and replaced with a fresh one from the api once it's older than the ttl
+    fn load_languages(&self) {
+        let path = data_dir().map(|dir| dir.join("languages.json"));
+        let ttl = std::time::Duration::from_secs(self.config_number("Language list TTL (seconds)", DEFAULT_LANGUAGE_LIST_TTL));
+
+        let stored = path.as_deref().and_then(languages::LanguageList::load);
+        let expired = stored.as_ref().map(|list| list.is_expired(ttl)).unwrap_or(true);
+        if let Some(list) = stored {
+            languages::set(list);
+        }
+
+        if !expired || self.is_offline() {
+            return;
+        }
+
+        // two requests with their retries can take a while, so loading the config doesn't wait for them
+        // the list in use stays until a new one has been fetched
+        let backend = self.backend.clone();
+        let logger = self.logger.clone();
+        std::thread::spawn(move || match languages::LanguageList::fetch(backend.as_ref()) {
+            Ok(list) => {
+                if let Some(path) = &path {
+                    if let Err(e) = list.save(path) {
+                        logger.warn(&format!("Failed to save the language list: {}", e));
                     }
-                };
+                }
+                languages::set(list);
+            }
+            // without a key there's nothing to fetch yet, the built in languages will do
+            Err(DeepLError::NoApiKey) => {}
+            Err(e) => logger.warn(&format!("Failed to fetch the supported languages, using the built in ones: {}", e)),
+        });
+    }
+
+    fn load_language_aliases(&self) {
+        let aliases = self.config.get("Language aliases").and_then(|entry| entry.as_string()).unwrap_or_default().to_string();
+        for warning in languages::set_user_aliases(&aliases) {
+            self.logger.warn(&warning);
+        }
+    }
+
+    fn cached(&self, key: Option<&str>, allow_expired: bool) -> Option<TranslateResponse> {
+        let key = key?;
+        self.cache.lock().ok().and_then(|mut cache| cache.get(key, allow_expired))
+    }
 
-                TranslateRequest {
-                    text: vec![rest.clone()],
-                    target_lang: target,
-                    source_lang: None,
+    fn is_offline(&self) -> bool {
+        let offline_mode = self.config.get("Offline mode").and_then(|entry| entry.as_bool()).unwrap_or(false);
+        offline_mode || self.offline.load(Ordering::Relaxed)
+    }
+
+    // only serve cached translations until a background probe manages to reach the api again
+    fn go_offline(&self) {
+        if self.offline.swap(true, Ordering::Relaxed) {
+            // already offline, so a probe is already running
+            return;
+        }
+
+        self.logger.warn("Failed to reach DeepL, serving cached translations until it is back");
+
+        let backend = self.backend.clone();
+        let offline = self.offline.clone();
+        std::thread::spawn(move || {
+            loop {
+                std::thread::sleep(OFFLINE_PROBE_INTERVAL);
+
+                // checking the usage doesn't cost any characters, and any answer at all means the api is reachable again
+                if !matches!(backend.usage(), Err(DeepLError::Connection(_))) {
+                    break;
                 }
             }
-            _ => {
-                // if return_error_messages {
-                //     res.push(SearchResult::new("Invalid query").set_context("No target language code"));
-                // }
-                self.logger.warn("No target language code");
-                return res.into();
+
+            offline.store(false, Ordering::Relaxed);
+        });
+    }
+
+    // (re)reads the persistent cache, so changes to its config take effect without a restart
+    fn load_disk_cache(&self) {
+        let use_persistent_cache = self.config.get("Persistent cache").and_then(|entry| entry.as_bool()).unwrap_or(true);
+        let size = self.config_number("Persistent cache size", DEFAULT_PERSISTENT_CACHE_SIZE) as usize;
+
+        let disk = match data_dir() {
+            Some(dir) if use_persistent_cache && size > 0 => match DiskCache::load(dir.join("translations.jsonl"), size) {
+                Ok(disk) => Some(disk),
+                Err(e) => {
+                    self.logger.warn(&format!("Failed to load the persistent cache: {}", e));
+                    None
+                }
+            },
+            Some(_) => None,
+            None => {
+                self.logger.warn("No data directory found, translations won't be cached across restarts");
+                None
             }
         };
 
-        let use_free_tier = self.config.get("Use free tier").and_then(|entry| entry.as_bool()).unwrap_or(true);
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.set_disk_cache(disk);
+        }
+    }
+
+    // `generation` identifies this search, once a newer one has started this one is stale and its results are thrown away
+    fn translate_query(&self, query: &str, generation: u64) -> Result<Vec<SearchResult>, DeepLError> {
+        let mut res: Vec<SearchResult> = vec![];
 
-        let response = match self
-            .client
-            .post(if use_free_tier {
-                "https://api-free.deepl.com/v2/translate"
-            } else {
-                "https://api.deepl.com/v2/translate"
-            })
-            .header("Authorization", format!("DeepL-Auth-Key {}", api_key))
-            .json(&query)
-            .send()
-        {
-            Ok(response) => response,
-            Err(e) => {
-                // if return_error_messages {
-                //     res.push(SearchResult::new("Request failed").set_context(&format!("Failed to send request: {}", e)));
-                // }
-                self.logger.error(&format!("Failed to send request: {}", e));
-                return res.into();
+        // the query is one of:
+        // <target_language_code>: <query>
+        // <source_language_code> -> <target_language_code>: <query>
+        // where <target_language_code> can also be a comma separated list of codes, e.g. de,fr,ja: <query>
+        // and every code can be followed by a formality, e.g. de!formal: <query>, or de!both: <query> for a formal and an informal translation
+        // and the codes can be followed by options for this query only, e.g. de [formal, glossary=product, nosplit]: <query> or en->ja +html: <query>
+        // and [back] or +back adds back-translations for checking the translations, e.g. de +back: <query>
+        // and <query> can end with context that helps with ambiguous words, e.g. de: bank || context: river side
+        // if a default target language is set, a plain <query> is valid too, but only after the trigger prefix if there is one
+        // see the parser module for the details, glossary and usage commands (glossaries:, ?usage, ...) are handled before any of that
+
+        // a trigger prefix keeps plain text typed into the launcher from being translated (and costing quota), e.g. "tr hello"
+        // queries with a header of their own are meant for us either way, so they work with or without it
+        let trigger_prefix = self.config.get("Trigger prefix").and_then(|entry| entry.as_string()).unwrap_or_default().to_string();
+        let (query, allow_default) = if trigger_prefix.trim().is_empty() {
+            (query, true)
+        } else {
+            match query.strip_prefix(trigger_prefix.as_str()) {
+                Some(query) => (query, true),
+                None => (query, false),
             }
         };
 
-        let response = match response.json::<TranslateResponse>() {
-            Ok(response) => response,
-            Err(e) => {
-                // if return_error_messages {
-                //     res.push(SearchResult::new("Response failed").set_context(&format!("Failed to parse response: {}", e)));
-                // }
-                self.logger.error(&format!("Failed to parse response: {}", e));
-                return res.into();
+        // glossaries: / glossary create: <file> / glossary delete: <name> manage glossaries instead of translating
+        if let Some(results) = self.glossary_command(query) {
+            return results;
+        }
+
+        if let Some(results) = self.usage_command(query) {
+            return results;
+        }
+
+        // with my languages set, queries without a header go to the primary language first and to the secondary one if they already were in the primary
+        let my_languages = self.my_languages();
+        let default_target = match &my_languages {
+            _ if !allow_default => String::new(),
+            Ok(Some((primary, _))) => primary.code(),
+            Ok(None) => self
+                .config
+                .get("Default target language")
+                .and_then(|entry| entry.as_string())
+                .unwrap_or_default()
+                .to_string(),
+            // any language tells the parser that plain text is ours, whether the broken setting matters is up to the query
+            Err(_) => TargetLanguageCode::EN.code(),
+        };
+
+        let parsed = match parser::parse(query, &default_target)? {
+            Some(parsed) => parsed,
+            None => return Ok(res),
+        };
+
+        // a query with a header of its own doesn't need my languages, so a broken setting only stops the ones without
+        let my_languages = match my_languages {
+            Ok(my_languages) => my_languages,
+            Err(e) if parsed.uses_default => return Err(e),
+            Err(_) => None,
+        };
+        let rest = parsed.text;
+
+        let default_formality = self.default_formality();
+
+        // the current context applies to every query that doesn't bring its own
+        let current_context = self
+            .config
+            .get("Current context")
+            .and_then(|entry| entry.as_string())
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        let context = parsed.options.context.clone().or_else(|| Some(current_context).filter(|context| !context.is_empty()));
+
+        let show_both_formalities = self.config.get("Show formal and informal variants").and_then(|entry| entry.as_bool()).unwrap_or(false);
+
+        let build_requests = |targets: Vec<Target>, source_lang: Option<SourceLanguageCode>| -> Result<Vec<TranslateRequest>, DeepLError> {
+            let mut requests = vec![];
+            for Target { language: target, modifier } in targets {
+                let formalities = match modifier {
+                    // both gives one result per formality, so the right register can be picked from the results
+                    Some(Modifier::Both) => vec![Some(Formality::More), Some(Formality::Less)],
+                    Some(Modifier::Formality(formality)) => vec![Some(formality)],
+                    None if show_both_formalities && target.supports_formality() => vec![Some(Formality::More), Some(Formality::Less)],
+                    // the default applies to every target, so only use it where the language supports it
+                    None => vec![default_formality.filter(|formality| !formality.is_strict() || target.supports_formality())],
+                };
+
+                for formality in formalities {
+                    requests.push(TranslateRequest {
+                        text: vec![rest.clone()],
+                        target_lang: target.clone(),
+                        source_lang: source_lang.clone(),
+                        formality,
+                        glossary_id: None,
+                        split_sentences: parsed.options.split_sentences,
+                        preserve_formatting: parsed.options.preserve_formatting,
+                        tag_handling: parsed.options.tag_handling,
+                        context: context.clone(),
+                    });
+                }
             }
+
+            if requests.is_empty() {
+                return Err(DeepLError::NoTargetLanguage);
+            }
+
+            match &parsed.options.glossary {
+                Some(GlossaryOption::Named(name)) => {
+                    for request in requests.iter_mut() {
+                        self.use_named_glossary(name, request)?;
+                    }
+                }
+                Some(GlossaryOption::Off) => {}
+                None => self.attach_glossaries(&mut requests),
+            }
+
+            Ok(requests)
         };
 
+        let mut requests = build_requests(parsed.targets, parsed.source.clone())?;
+
+        // wait a moment before going to the api in case another keystroke is coming, cached and offline translations are instant anyway
+        let debounce = std::time::Duration::from_millis(self.config_number("Debounce (ms)", DEFAULT_DEBOUNCE_MS));
+        if !debounce.is_zero() && !self.is_offline() && requests.iter().any(|request| self.cached(cache::cache_key(request).as_deref(), false).is_none()) {
+            std::thread::sleep(debounce);
+
+            if self.is_stale(generation) {
+                self.logger.trace("Query was superseded before it was sent");
+                return Ok(res);
+            }
+        }
+
+        let mut responses = self.translate_all(&requests);
+
+        // the translations are cached by now so they aren't wasted, but a newer query is going to show its own results
+        if self.is_stale(generation) {
+            self.logger.trace("Query was superseded while it was being translated");
+            return Ok(res);
+        }
+
+        // text that already is in the primary language gets translated into the secondary one instead, from the language deepl detected
+        let detected = responses
+            .iter()
+            .find_map(|response| response.as_ref().ok())
+            .and_then(|(response, _)| response.translations.first())
+            .map(|translation| translation.detected_source_language.clone());
+        if let (Some((primary, secondary)), Some(detected), true) = (&my_languages, detected, parsed.uses_default) {
+            if primary.is_language_of(&detected) {
+                let target = Target {
+                    language: secondary.clone(),
+                    modifier: None,
+                };
+                requests = build_requests(vec![target], Some(detected))?;
+                responses = self.translate_all(&requests);
+
+                if self.is_stale(generation) {
+                    self.logger.trace("Query was superseded while it was being translated");
+                    return Ok(res);
+                }
+            }
+        }
+
+        let responses = responses.into_iter().collect::<Result<Vec<_>, _>>()?;
+
+        // every back-translation goes right below the translation it was made from
+        let show_back_translations = self.config.get("Show back-translations").and_then(|entry| entry.as_bool()).unwrap_or(false);
+        let mut back_requests = vec![];
+        if show_back_translations || parsed.options.back_translate {
+            back_requests = requests
+                .iter()
+                .zip(&responses)
+                .map(|(request, (response, _))| self.back_request(request, response))
+                .collect::<Vec<_>>();
+        }
+        let mut back_responses = self.translate_all(&back_requests.iter().flatten().cloned().collect::<Vec<_>>()).into_iter();
+
+        if self.is_stale(generation) {
+            self.logger.trace("Query was superseded while it was being translated");
+            return Ok(res);
+        }
+
+        for (i, (request, (response, cached))) in requests.iter().zip(responses).enumerate() {
+            res.extend(self.build_results(request, response, &rest, cached));
+
+            if let Some(Some(back_request)) = back_requests.get(i) {
+                if let Some(back_response) = back_responses.next() {
+                    res.extend(self.back_translation_results(back_request, back_response));
+                }
+            }
+        }
+
+        Ok(res)
+    }
+
+    // deepl only takes one target language per request, so send them all at once and wait for every one of them
+    fn translate_all(&self, requests: &[TranslateRequest]) -> Vec<Result<(TranslateResponse, bool), DeepLError>> {
+        std::thread::scope(|scope| {
+            let handles = requests.iter().map(|request| scope.spawn(|| self.translate_cached(request))).collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap_or_else(|_| Err(DeepLError::Request("Translation thread panicked".to_owned()))))
+                .collect::<Vec<_>>()
+        })
+    }
+
+    // "My languages" as (primary, secondary), e.g. EN-US, DE
+    fn my_languages(&self) -> Result<Option<(TargetLanguageCode, TargetLanguageCode)>, DeepLError> {
+        let setting = self.config.get("My languages").and_then(|entry| entry.as_string()).unwrap_or_default().to_string();
+        let languages = setting
+            .split(',')
+            .map(|language| language.trim())
+            .filter(|language| !language.is_empty())
+            .collect::<Vec<_>>();
+
+        let in_setting = |e: DeepLError| DeepLError::InQuery(Box::new(e), format!("\"My languages\" ({})", setting.trim()));
+        match languages.as_slice() {
+            [] => Ok(None),
+            [primary, secondary] => {
+                let primary = primary.parse::<TargetLanguageCode>().map_err(in_setting)?;
+                let secondary = secondary.parse::<TargetLanguageCode>().map_err(in_setting)?;
+                if primary == secondary {
+                    return Err(DeepLError::InvalidMyLanguages(setting.trim().to_owned()));
+                }
+                Ok(Some((primary, secondary)))
+            }
+            _ => Err(DeepLError::InvalidMyLanguages(setting.trim().to_owned())),
+        }
+    }
+
+    fn is_stale(&self, generation: u64) -> bool {
+        self.generation.load(Ordering::SeqCst) != generation
+    }
+
+    fn build_results(&self, request: &TranslateRequest, response: TranslateResponse, rest: &str, cached: bool) -> Vec<SearchResult> {
+        let mut res: Vec<SearchResult> = vec![];
+
         // by default, the clipboard will only contain the translated text
 
         // if true, the clipboard will contain the query, a newline, and the translated text
@@ -195,10 +607,23 @@ impl Searchable for DeepL {
         // if true, then format the query as <source_language_code>: <query> (if included) and format the translated text as <target_language_code>: <translated_text>
         let include_language_code_in_clipboard = self.config.get("Include language code in clipboard").and_then(|entry| entry.as_bool()).unwrap_or(false);
 
+        // label every result with its target language (and formality) so translations into several languages can be told apart
+        let mut context = match request.formality {
+            Some(formality) if formality != Formality::Default => format!("{} ({})", request.target_lang, formality),
+            _ => request.target_lang.to_string(),
+        };
+        // a sticky context is easy to forget about, so always show the one that was used
+        if let Some(translation_context) = &request.context {
+            context.push_str(&format!(", context: {}", translation_context));
+        }
+        if cached {
+            context.push_str(" (cached)");
+        }
+
         for translation in response.translations {
             let query_str = if include_query_in_clipboard {
                 if include_language_code_in_clipboard {
-                    let source_lang = query.source_lang.unwrap_or(translation.detected_source_language);
+                    let source_lang = request.source_lang.clone().unwrap_or(translation.detected_source_language);
                     format!("{}: {}\n", source_lang, rest)
                 } else {
                     format!("{}\n", rest)
@@ -208,17 +633,94 @@ impl Searchable for DeepL {
             };
 
             let translated_str = if include_language_code_in_clipboard {
-                format!("{}: {}", query.target_lang, translation.text)
+                format!("{}: {}", request.target_lang, translation.text)
             } else {
                 translation.text.clone()
             };
 
             let clipboard_text = format!("{}{}", query_str, translated_str);
 
-            res.push(SearchResult::new(&translation.text).set_extra_info(&clipboard_text));
+            res.push(SearchResult::new(&translation.text).set_context(&context).set_extra_info(&clipboard_text));
         }
 
-        res.into()
+        res
+    }
+
+    fn run_action(&self, action: Action) {
+        match action {
+            Action::CreateGlossary { path } => match self.create_glossary_from_file(std::path::Path::new(&path)) {
+                Ok(glossary) => self.logger.trace(&format!("created glossary {} ({})", glossary.name, glossary.glossary_id)),
+                Err(e) => self.log_error(&e),
+            },
+            Action::DeleteGlossary { glossary_id } => match self.delete_glossary(&glossary_id) {
+                Ok(()) => self.logger.trace(&format!("deleted glossary {}", glossary_id)),
+                Err(e) => self.log_error(&e),
+            },
+        }
+    }
+
+    fn log_error(&self, e: &DeepLError) {
+        match e.kind() {
+            DeepLError::NoQuery => self.logger.trace(&e.to_string()),
+            DeepLError::NoTargetLanguage
+            | DeepLError::TooManyArrows
+            | DeepLError::InvalidSourceLanguage(_)
+            | DeepLError::InvalidTargetLanguage(_)
+            | DeepLError::AmbiguousLanguage(_, _)
+            | DeepLError::MisspelledLanguage(_, _)
+            | DeepLError::InvalidFormality(_)
+            | DeepLError::InvalidMyLanguages(_)
+            | DeepLError::UnknownFlag(_)
+            | DeepLError::InvalidFlagValue(_, _)
+            | DeepLError::UnclosedOptions
+            | DeepLError::FormalityNotSupported(_)
+            | DeepLError::BudgetExceeded(_, _) => self.logger.warn(&e.to_string()),
+            _ => self.logger.error(&e.to_string()),
+        }
+    }
+}
+
+impl Searchable for DeepL {
+    fn search(&self, query: RString) -> RVec<SearchResult> {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+
+        match self.translate_query(&query, generation) {
+            Ok(res) => res.into(),
+            Err(e) => {
+                self.log_error(&e);
+
+                // suggestions are results in their own right rather than an error message
+                // a name that fits several languages is always answered with them, a word that only comes close to some may not have been meant as a language at all
+                let return_error_messages = self.config.get("Return Error messages").and_then(|entry| entry.as_bool()).unwrap_or(false);
+                let suggestions = match e.kind() {
+                    DeepLError::AmbiguousLanguage(input, candidates) => Some((input, candidates)),
+                    DeepLError::MisspelledLanguage(input, suggestions) if return_error_messages => Some((input, suggestions)),
+                    _ => None,
+                };
+                if let Some((input, suggestions)) = suggestions {
+                    return suggestions
+                        .iter()
+                        .map(|(code, name)| {
+                            SearchResult::new(name)
+                                .set_context(&format!("Did you mean {} for \"{}\"? Use {}: instead", name, input, code.to_lowercase()))
+                                .set_extra_info(&code.to_lowercase())
+                        })
+                        .collect();
+                }
+
+                // a refusal because of the budget is always shown, otherwise it would look like the plugin stopped working
+                // and so is a bad option, it was typed on purpose and should say what's wrong with it
+                let always_shown = matches!(
+                    e.kind(),
+                    DeepLError::BudgetExceeded(_, _) | DeepLError::UnknownFlag(_) | DeepLError::InvalidFlagValue(_, _) | DeepLError::UnclosedOptions
+                );
+                if return_error_messages || always_shown {
+                    vec![SearchResult::new(e.title()).set_context(&e.to_string())].into()
+                } else {
+                    RVec::new()
+                }
+            }
+        }
     }
     fn name(&self) -> RStr<'static> {
         NAME.into()
@@ -229,6 +731,12 @@ impl Searchable for DeepL {
     }
     fn execute(&self, result: &SearchResult) {
         let extra_info = result.extra_info();
+
+        if let Some(action) = Action::decode(extra_info) {
+            self.run_action(action);
+            return;
+        }
+
         if !extra_info.is_empty() {
             if let Ok::<clipboard::ClipboardContext, Box<dyn std::error::Error>>(mut clipboard) = clipboard::ClipboardProvider::new() {
                 if let Ok(()) = clipboard::ClipboardProvider::set_contents(&mut clipboard, extra_info.to_owned()) {
@@ -250,61 +758,154 @@ impl Searchable for DeepL {
         default_config()
     }
     fn lazy_load_config(&mut self, config: quick_search_lib::Config) {
+        self.backend.reload_config(&config);
         self.config = config;
+
+        let capacity = self.config_number("Cache capacity", DEFAULT_CACHE_CAPACITY as u64) as usize;
+        let ttl = std::time::Duration::from_secs(self.config_number("Cache TTL (seconds)", DEFAULT_CACHE_TTL));
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.resize(capacity, ttl);
+        }
+
+        self.load_disk_cache();
+        self.load_budget();
+        self.load_languages();
+        self.load_language_aliases();
+        self.forget_glossaries();
+        self.sync_glossary_dir();
+    }
+}
+
+// the config only has string entries for numbers, so parse them here
+// empty or missing entries give the default, invalid ones give back the value that failed to parse
+fn config_number(config: &quick_search_lib::Config, key: &str, default: u64) -> Result<u64, String> {
+    match config.get(key).and_then(|entry| entry.as_string()) {
+        Some(value) if !value.trim().is_empty() => value.trim().parse().map_err(|_| value.to_string()),
+        _ => Ok(default),
     }
 }
 
+// where everything the plugin keeps between launcher restarts lives
+#[cfg(not(test))]
+fn data_dir() -> Option<std::path::PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("quick-search").join("deepl-plugin"))
+}
+
+// the tests keep everything in memory rather than touching the real data directory
+#[cfg(test)]
+fn data_dir() -> Option<std::path::PathBuf> {
+    None
+}
+
 fn default_config() -> quick_search_lib::Config {
     let mut config = quick_search_lib::Config::new();
     config.insert("DeepL Api Key".into(), quick_search_lib::EntryType::String { value: RString::new() });
     config.insert("Use free tier".into(), quick_search_lib::EntryType::Bool { value: true });
-    // config.insert("Return Error messages".into(), quick_search_lib::EntryType::Bool { value: false });
+    // when set, overrides the endpoint chosen by "Use free tier"
+    config.insert("Custom API base URL".into(), quick_search_lib::EntryType::String { value: RString::new() });
+    // the default target language is used for queries without a language prefix, e.g. EN-US or DE
+    config.insert("Default target language".into(), quick_search_lib::EntryType::String { value: RString::new() });
+    // a primary and a secondary language, e.g. "EN-US, DE", queries without a language prefix go to the primary one, or to the secondary one if they are in the primary one already
+    // takes the place of the default target language when set
+    config.insert("My languages".into(), quick_search_lib::EntryType::String { value: RString::new() });
+    // extra names for languages, e.g. "br=pt-br, us=en-us, cn=zh, nor=nb", these win over the built in names
+    config.insert("Language aliases".into(), quick_search_lib::EntryType::String { value: RString::new() });
+    // sent as context with every query that has none of its own, e.g. "finance" so "bank" isn't taken for a river bank, leave empty when done
+    config.insert("Current context".into(), quick_search_lib::EntryType::String { value: RString::new() });
+    // when set, only queries starting with this prefix are translated, e.g. "tr "
+    config.insert("Trigger prefix".into(), quick_search_lib::EntryType::String { value: RString::new() });
+    // formality for targets that support it, one of: more, less, prefer_more, prefer_less
+    config.insert("Default formality".into(), quick_search_lib::EntryType::String { value: RString::new() });
+    // show each translation translated back into the language of the query right below it, to check what it says before pasting it
+    // costs a second translation per result, the back flag does the same for a single query, e.g. de +back: <query>
+    config.insert("Show back-translations".into(), quick_search_lib::EntryType::Bool { value: false });
+    // show a formal and an informal translation for targets that support formality
+    config.insert("Show formal and informal variants".into(), quick_search_lib::EntryType::Bool { value: false });
+    // attach the account's glossary for the language pair to translations that have a source language
+    config.insert("Use glossaries".into(), quick_search_lib::EntryType::Bool { value: true });
+    // directory of <source>-<target>.tsv or .csv term files, e.g. en-de.tsv, uploaded as glossaries whenever they change
+    config.insert("Glossary directory".into(), quick_search_lib::EntryType::String { value: RString::new() });
+    config.insert("Return Error messages".into(), quick_search_lib::EntryType::Bool { value: false });
+    // number of translations kept in memory, 0 disables the cache
+    config.insert(
+        "Cache capacity".into(),
+        quick_search_lib::EntryType::String {
+            value: DEFAULT_CACHE_CAPACITY.to_string().into(),
+        },
+    );
+    // how long a cached translation stays valid, 0 keeps them until they are evicted
+    config.insert(
+        "Cache TTL (seconds)".into(),
+        quick_search_lib::EntryType::String {
+            value: DEFAULT_CACHE_TTL.to_string().into(),
+        },
+    );
+    // how long to wait for more typing before a query is sent, 0 sends every query straight away
+    config.insert(
+        "Debounce (ms)".into(),
+        quick_search_lib::EntryType::String {
+            value: DEFAULT_DEBOUNCE_MS.to_string().into(),
+        },
+    );
+    config.insert(
+        "Connect timeout (ms)".into(),
+        quick_search_lib::EntryType::String {
+            value: DEFAULT_CONNECT_TIMEOUT_MS.to_string().into(),
+        },
+    );
+    // covers the whole request, from connecting until the response body has been read
+    config.insert(
+        "Request timeout (ms)".into(),
+        quick_search_lib::EntryType::String {
+            value: DEFAULT_REQUEST_TIMEOUT_MS.to_string().into(),
+        },
+    );
+    // how often a throttled (429) or failed (5xx) request is retried, requests with a bad key (403) or no quota left (456) never are
+    config.insert(
+        "Max retries".into(),
+        quick_search_lib::EntryType::String {
+            value: DEFAULT_MAX_RETRIES.to_string().into(),
+        },
+    );
+    // only serve cached translations and never contact the api
+    config.insert("Offline mode".into(), quick_search_lib::EntryType::Bool { value: false });
+    // keeps translations in a file in the data directory so they survive restarts
+    config.insert("Persistent cache".into(), quick_search_lib::EntryType::Bool { value: true });
+    // number of translations kept on disk
+    config.insert(
+        "Persistent cache size".into(),
+        quick_search_lib::EntryType::String {
+            value: DEFAULT_PERSISTENT_CACHE_SIZE.to_string().into(),
+        },
+    );
+    // characters this plugin may get billed for per calendar month, translations are refused once it is used up, 0 means no budget
+    config.insert(
+        "Monthly character budget".into(),
+        quick_search_lib::EntryType::String {
+            value: DEFAULT_MONTHLY_CHARACTER_BUDGET.to_string().into(),
+        },
+    );
+    // ?usage warns once this much of the character limit is used, 0 turns the warning off
+    config.insert(
+        "Usage warning threshold (%)".into(),
+        quick_search_lib::EntryType::String {
+            value: DEFAULT_USAGE_WARNING_THRESHOLD.to_string().into(),
+        },
+    );
+    // how long the list of supported languages fetched from DeepL is kept before asking again, 0 keeps it forever
+    config.insert(
+        "Language list TTL (seconds)".into(),
+        quick_search_lib::EntryType::String {
+            value: DEFAULT_LANGUAGE_LIST_TTL.to_string().into(),
+        },
+    );
     config.insert("Include query in clipboard".into(), quick_search_lib::EntryType::Bool { value: false });
     config.insert("Include language code in clipboard".into(), quick_search_lib::EntryType::Bool { value: false });
     config
 }
 
-// example request:
-// POST /v2/translate HTTP/2
-// Host: api-free.deepl.com
-// Authorization: DeepL-Auth-Key [yourAuthKey]
-// User-Agent: YourApp/1.2.3
-// Content-Length: 45
-// Content-Type: application/json
-
-// {"text":["Hello, world!"],"target_lang":"DE"}
-
-#[derive(Debug, Serialize)]
-struct TranslateRequest {
-    text: Vec<String>,
-    target_lang: TargetLanguageCode,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    source_lang: Option<SourceLanguageCode>,
-}
-
-// example response:
-// {
-//   "translations": [
-//     {
-//       "detected_source_language": "EN",
-//       "text": "Hallo, Welt!"
-//     }
-//   ]
-// }
-
-#[derive(Debug, Deserialize)]
-struct TranslateResponse {
-    translations: Vec<TranslatedText>,
-}
-
-#[derive(Debug, Deserialize)]
-struct TranslatedText {
-    detected_source_language: SourceLanguageCode,
-    text: String,
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
-enum SourceLanguageCode {
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum SourceLanguageCode {
     AR, // Arabic [1]
     BG, // Bulgarian
     CS, // Czech
@@ -335,116 +936,47 @@ enum SourceLanguageCode {
     TR, // Turkish
     UK, // Ukrainian
     ZH, // Chinese
+    // anything DeepL supports that isn't listed above, see the languages module
+    #[serde(untagged)]
+    Other(String),
 }
 
 impl SourceLanguageCode {
-    fn guess_from_str(s: &str) -> Option<Self> {
-        Some(match s {
-            "ar" => SourceLanguageCode::AR,
-            "arabic" => SourceLanguageCode::AR,
-            "bg" => SourceLanguageCode::BG,
-            "bulgarian" => SourceLanguageCode::BG,
-            "cs" => SourceLanguageCode::CS,
-            "czech" => SourceLanguageCode::CS,
-            "da" => SourceLanguageCode::DA,
-            "danish" => SourceLanguageCode::DA,
-            "de" => SourceLanguageCode::DE,
-            "german" => SourceLanguageCode::DE,
-            "el" => SourceLanguageCode::EL,
-            "greek" => SourceLanguageCode::EL,
-            "en" => SourceLanguageCode::EN,
-            "english" => SourceLanguageCode::EN,
-            "es" => SourceLanguageCode::ES,
-            "spanish" => SourceLanguageCode::ES,
-            "et" => SourceLanguageCode::ET,
-            "estonian" => SourceLanguageCode::ET,
-            "fi" => SourceLanguageCode::FI,
-            "finnish" => SourceLanguageCode::FI,
-            "fr" => SourceLanguageCode::FR,
-            "french" => SourceLanguageCode::FR,
-            "hu" => SourceLanguageCode::HU,
-            "hungarian" => SourceLanguageCode::HU,
-            "id" => SourceLanguageCode::ID,
-            "indonesian" => SourceLanguageCode::ID,
-            "it" => SourceLanguageCode::IT,
-            "italian" => SourceLanguageCode::IT,
-            "jp" => SourceLanguageCode::JA,
-            "ja" => SourceLanguageCode::JA,
-            "japanese" => SourceLanguageCode::JA,
-            "ko" => SourceLanguageCode::KO,
-            "korean" => SourceLanguageCode::KO,
-            "lt" => SourceLanguageCode::LT,
-            "lithuanian" => SourceLanguageCode::LT,
-            "lv" => SourceLanguageCode::LV,
-            "latvian" => SourceLanguageCode::LV,
-            "nb" => SourceLanguageCode::NB,
-            "norwegian" => SourceLanguageCode::NB,
-            "nl" => SourceLanguageCode::NL,
-            "dutch" => SourceLanguageCode::NL,
-            "pl" => SourceLanguageCode::PL,
-            "polish" => SourceLanguageCode::PL,
-            "pt" => SourceLanguageCode::PT,
-            "portuguese" => SourceLanguageCode::PT,
-            "ro" => SourceLanguageCode::RO,
-            "romanian" => SourceLanguageCode::RO,
-            "ru" => SourceLanguageCode::RU,
-            "russian" => SourceLanguageCode::RU,
-            "sk" => SourceLanguageCode::SK,
-            "slovak" => SourceLanguageCode::SK,
-            "sl" => SourceLanguageCode::SL,
-            "slovenian" => SourceLanguageCode::SL,
-            "sv" => SourceLanguageCode::SV,
-            "swedish" => SourceLanguageCode::SV,
-            "tr" => SourceLanguageCode::TR,
-            "turkish" => SourceLanguageCode::TR,
-            "uk" => SourceLanguageCode::UK,
-            "ukrainian" => SourceLanguageCode::UK,
-            "zh" => SourceLanguageCode::ZH,
-            "chinese" => SourceLanguageCode::ZH,
-            _ => return None,
-        })
+    // codes without a variant of their own end up as Other
+    fn from_code(code: &str) -> Self {
+        serde_json::from_value(serde_json::Value::String(code.to_owned())).unwrap_or_else(|_| SourceLanguageCode::Other(code.to_owned()))
+    }
+
+    // the code as the api knows it, e.g. "DE"
+    pub fn code(&self) -> String {
+        match self {
+            SourceLanguageCode::Other(code) => code.clone(),
+            _ => serde_json::to_value(self).ok().and_then(|value| value.as_str().map(|s| s.to_owned())).unwrap_or_default(),
+        }
+    }
+}
+
+impl std::str::FromStr for SourceLanguageCode {
+    type Err = DeepLError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match languages::match_language(LanguageType::Source, s) {
+            languages::LanguageMatch::Found(code) => Ok(Self::from_code(&code)),
+            languages::LanguageMatch::Candidates(candidates) => Err(DeepLError::AmbiguousLanguage(s.trim().to_owned(), candidates)),
+            languages::LanguageMatch::Suggestions(suggestions) => Err(DeepLError::MisspelledLanguage(s.trim().to_owned(), suggestions)),
+            languages::LanguageMatch::NotFound => Err(DeepLError::InvalidSourceLanguage(s.trim().to_lowercase())),
+        }
     }
 }
 
 impl std::fmt::Display for SourceLanguageCode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            SourceLanguageCode::AR => write!(f, "Arabic"),
-            SourceLanguageCode::BG => write!(f, "Bulgarian"),
-            SourceLanguageCode::CS => write!(f, "Czech"),
-            SourceLanguageCode::DA => write!(f, "Danish"),
-            SourceLanguageCode::DE => write!(f, "German"),
-            SourceLanguageCode::EL => write!(f, "Greek"),
-            SourceLanguageCode::EN => write!(f, "English"),
-            SourceLanguageCode::ES => write!(f, "Spanish"),
-            SourceLanguageCode::ET => write!(f, "Estonian"),
-            SourceLanguageCode::FI => write!(f, "Finnish"),
-            SourceLanguageCode::FR => write!(f, "French"),
-            SourceLanguageCode::HU => write!(f, "Hungarian"),
-            SourceLanguageCode::ID => write!(f, "Indonesian"),
-            SourceLanguageCode::IT => write!(f, "Italian"),
-            SourceLanguageCode::JA => write!(f, "Japanese"),
-            SourceLanguageCode::KO => write!(f, "Korean"),
-            SourceLanguageCode::LT => write!(f, "Lithuanian"),
-            SourceLanguageCode::LV => write!(f, "Latvian"),
-            SourceLanguageCode::NB => write!(f, "Norwegian (Bokmål)"),
-            SourceLanguageCode::NL => write!(f, "Dutch"),
-            SourceLanguageCode::PL => write!(f, "Polish"),
-            SourceLanguageCode::PT => write!(f, "Portuguese"),
-            SourceLanguageCode::RO => write!(f, "Romanian"),
-            SourceLanguageCode::RU => write!(f, "Russian"),
-            SourceLanguageCode::SK => write!(f, "Slovak"),
-            SourceLanguageCode::SL => write!(f, "Slovenian"),
-            SourceLanguageCode::SV => write!(f, "Swedish"),
-            SourceLanguageCode::TR => write!(f, "Turkish"),
-            SourceLanguageCode::UK => write!(f, "Ukrainian"),
-            SourceLanguageCode::ZH => write!(f, "Chinese"),
-        }
+        write!(f, "{}", languages::name(LanguageType::Source, &self.code()))
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
-enum TargetLanguageCode {
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum TargetLanguageCode {
     AR, // Arabic [1]
     BG, // Bulgarian
     CS, // Czech
@@ -483,118 +1015,803 @@ enum TargetLanguageCode {
     TR, // Turkish
     UK, // Ukrainian
     ZH, // Chinese (simplified)
+    // anything DeepL supports that isn't listed above, e.g. ZH-HANT or ES-419, see the languages module
+    #[serde(untagged)]
+    Other(String),
 }
 
 impl TargetLanguageCode {
-    fn guess_from_str(s: &str) -> Option<Self> {
-        Some(match s {
-            "ar" => TargetLanguageCode::AR,
-            "arabic" => TargetLanguageCode::AR,
-            "bg" => TargetLanguageCode::BG,
-            "bulgarian" => TargetLanguageCode::BG,
-            "cs" => TargetLanguageCode::CS,
-            "czech" => TargetLanguageCode::CS,
-            "da" => TargetLanguageCode::DA,
-            "danish" => TargetLanguageCode::DA,
-            "de" => TargetLanguageCode::DE,
-            "german" => TargetLanguageCode::DE,
-            "el" => TargetLanguageCode::EL,
-            "greek" => TargetLanguageCode::EL,
-            "en" => TargetLanguageCode::EN,
-            "english" => TargetLanguageCode::EN,
-            "en-gb" => TargetLanguageCode::EnGb,
-            "en-us" => TargetLanguageCode::EnUs,
-            "es" => TargetLanguageCode::ES,
-            "spanish" => TargetLanguageCode::ES,
-            "et" => TargetLanguageCode::ET,
-            "estonian" => TargetLanguageCode::ET,
-            "fi" => TargetLanguageCode::FI,
-            "finnish" => TargetLanguageCode::FI,
-            "fr" => TargetLanguageCode::FR,
-            "french" => TargetLanguageCode::FR,
-            "hu" => TargetLanguageCode::HU,
-            "hungarian" => TargetLanguageCode::HU,
-            "id" => TargetLanguageCode::ID,
-            "indonesian" => TargetLanguageCode::ID,
-            "it" => TargetLanguageCode::IT,
-            "italian" => TargetLanguageCode::IT,
-            "jp" => TargetLanguageCode::JA,
-            "ja" => TargetLanguageCode::JA,
-            "japanese" => TargetLanguageCode::JA,
-            "ko" => TargetLanguageCode::KO,
-            "korean" => TargetLanguageCode::KO,
-            "lt" => TargetLanguageCode::LT,
-            "lithuanian" => TargetLanguageCode::LT,
-            "lv" => TargetLanguageCode::LV,
-            "latvian" => TargetLanguageCode::LV,
-            "nb" => TargetLanguageCode::NB,
-            "norwegian" => TargetLanguageCode::NB,
-            "nl" => TargetLanguageCode::NL,
-            "dutch" => TargetLanguageCode::NL,
-            "pl" => TargetLanguageCode::PL,
-            "polish" => TargetLanguageCode::PL,
-            "pt" => TargetLanguageCode::PT,
-            "portuguese" => TargetLanguageCode::PT,
-            "pt-br" => TargetLanguageCode::PtBr,
-            "pt-pt" => TargetLanguageCode::PtPt,
-            "ro" => TargetLanguageCode::RO,
-            "romanian" => TargetLanguageCode::RO,
-            "ru" => TargetLanguageCode::RU,
-            "russian" => TargetLanguageCode::RU,
-            "sk" => TargetLanguageCode::SK,
-            "slovak" => TargetLanguageCode::SK,
-            "sl" => TargetLanguageCode::SL,
-            "slovenian" => TargetLanguageCode::SL,
-            "sv" => TargetLanguageCode::SV,
-            "swedish" => TargetLanguageCode::SV,
-            "tr" => TargetLanguageCode::TR,
-            "turkish" => TargetLanguageCode::TR,
-            "uk" => TargetLanguageCode::UK,
-            "ukrainian" => TargetLanguageCode::UK,
-            "zh" => TargetLanguageCode::ZH,
-            "chinese" => TargetLanguageCode::ZH,
-            _ => return None,
-        })
+    // codes without a variant of their own end up as Other
+    fn from_code(code: &str) -> Self {
+        serde_json::from_value(serde_json::Value::String(code.to_owned())).unwrap_or_else(|_| TargetLanguageCode::Other(code.to_owned()))
+    }
+
+    // the code as the api knows it, e.g. "EN-GB"
+    pub fn code(&self) -> String {
+        match self {
+            TargetLanguageCode::Other(code) => code.clone(),
+            _ => serde_json::to_value(self).ok().and_then(|value| value.as_str().map(|s| s.to_owned())).unwrap_or_default(),
+        }
+    }
+
+    // the languages DeepL can translate more or less formally
+    fn supports_formality(&self) -> bool {
+        languages::supports_formality(&self.code())
+    }
+
+    // whether text in `source` is already in this language, variants don't count, so EN text is in EN-US as well as in EN-GB
+    fn is_language_of(&self, source: &SourceLanguageCode) -> bool {
+        let base = |code: &str| code.split('-').next().unwrap_or_default().to_uppercase();
+        base(&self.code()) == base(&source.code())
+    }
+}
+
+impl std::str::FromStr for TargetLanguageCode {
+    type Err = DeepLError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match languages::match_language(LanguageType::Target, s) {
+            languages::LanguageMatch::Found(code) => Ok(Self::from_code(&code)),
+            languages::LanguageMatch::Candidates(candidates) => Err(DeepLError::AmbiguousLanguage(s.trim().to_owned(), candidates)),
+            languages::LanguageMatch::Suggestions(suggestions) => Err(DeepLError::MisspelledLanguage(s.trim().to_owned(), suggestions)),
+            languages::LanguageMatch::NotFound => Err(DeepLError::InvalidTargetLanguage(s.trim().to_lowercase())),
+        }
     }
 }
 
 impl std::fmt::Display for TargetLanguageCode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            TargetLanguageCode::AR => write!(f, "Arabic"),
-            TargetLanguageCode::BG => write!(f, "Bulgarian"),
-            TargetLanguageCode::CS => write!(f, "Czech"),
-            TargetLanguageCode::DA => write!(f, "Danish"),
-            TargetLanguageCode::DE => write!(f, "German"),
-            TargetLanguageCode::EL => write!(f, "Greek"),
-            TargetLanguageCode::EN => write!(f, "English"),
-            TargetLanguageCode::EnGb => write!(f, "English (British)"),
-            TargetLanguageCode::EnUs => write!(f, "English (American)"),
-            TargetLanguageCode::ES => write!(f, "Spanish"),
-            TargetLanguageCode::ET => write!(f, "Estonian"),
-            TargetLanguageCode::FI => write!(f, "Finnish"),
-            TargetLanguageCode::FR => write!(f, "French"),
-            TargetLanguageCode::HU => write!(f, "Hungarian"),
-            TargetLanguageCode::ID => write!(f, "Indonesian"),
-            TargetLanguageCode::IT => write!(f, "Italian"),
-            TargetLanguageCode::JA => write!(f, "Japanese"),
-            TargetLanguageCode::KO => write!(f, "Korean"),
-            TargetLanguageCode::LT => write!(f, "Lithuanian"),
-            TargetLanguageCode::LV => write!(f, "Latvian"),
-            TargetLanguageCode::NB => write!(f, "Norwegian (Bokmål)"),
-            TargetLanguageCode::NL => write!(f, "Dutch"),
-            TargetLanguageCode::PL => write!(f, "Polish"),
-            TargetLanguageCode::PT => write!(f, "Portuguese"),
-            TargetLanguageCode::PtBr => write!(f, "Portuguese (Brazilian)"),
-            TargetLanguageCode::PtPt => write!(f, "Portuguese (Other)"),
-            TargetLanguageCode::RO => write!(f, "Romanian"),
-            TargetLanguageCode::RU => write!(f, "Russian"),
-            TargetLanguageCode::SK => write!(f, "Slovak"),
-            TargetLanguageCode::SL => write!(f, "Slovenian"),
-            TargetLanguageCode::SV => write!(f, "Swedish"),
-            TargetLanguageCode::TR => write!(f, "Turkish"),
-            TargetLanguageCode::UK => write!(f, "Ukrainian"),
-            TargetLanguageCode::ZH => write!(f, "Chinese (simplified)"),
+        write!(f, "{}", languages::name(LanguageType::Target, &self.code()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{
+        backend::{Language, LanguageType, TranslateResponse, UsageResponse},
+        glossary::CreateGlossaryRequest,
+    };
+
+    // errors aren't Clone, so the fake makes a new one for every failed request
+    type MakeError = fn() -> DeepLError;
+
+    // answers every request with "[<target code>] <text>" and remembers what was sent, or fails with `error` when set
+    #[derive(Clone, Default)]
+    struct FakeBackend {
+        requests: Arc<Mutex<Vec<serde_json::Value>>>,
+        error: Arc<Mutex<Option<MakeError>>>,
+        glossaries: Arc<Mutex<Vec<GlossaryInfo>>>,
+        usage: Arc<Mutex<Option<serde_json::Value>>>,
+        // the language deepl says the text is in, EN unless set
+        detected: Arc<Mutex<Option<&'static str>>>,
+        // glossary ids are never reused, like deepl's
+        created_glossaries: Arc<AtomicU64>,
+        // how long asking for the supported languages takes, before it fails without an api key
+        languages_delay: Arc<Mutex<std::time::Duration>>,
+    }
+
+    impl TranslationBackend for FakeBackend {
+        fn translate(&self, request: &TranslateRequest) -> Result<TranslateResponse, DeepLError> {
+            let sent = serde_json::to_value(request).unwrap();
+            let target = sent["target_lang"].as_str().unwrap_or_default().to_owned();
+            self.requests.lock().unwrap().push(sent);
+            let detected = self.detected.lock().unwrap().unwrap_or("EN");
+            if let Some(error) = *self.error.lock().unwrap() {
+                return Err(error());
+            }
+
+            Ok(TranslateResponse {
+                translations: request
+                    .text
+                    .iter()
+                    .map(|text| serde_json::from_value(serde_json::json!({ "detected_source_language": detected, "text": format!("[{}] {}", target, text) })).unwrap())
+                    .collect(),
+            })
+        }
+
+        fn languages(&self, _language_type: LanguageType) -> Result<Vec<Language>, DeepLError> {
+            let delay = *self.languages_delay.lock().unwrap();
+            std::thread::sleep(delay);
+            Err(DeepLError::NoApiKey)
+        }
+
+        fn usage(&self) -> Result<UsageResponse, DeepLError> {
+            match self.usage.lock().unwrap().clone() {
+                Some(usage) => Ok(serde_json::from_value(usage).unwrap()),
+                None => Err(DeepLError::NoApiKey),
+            }
+        }
+
+        fn list_glossaries(&self) -> Result<Vec<GlossaryInfo>, DeepLError> {
+            Ok(self.glossaries.lock().unwrap().clone())
+        }
+
+        fn create_glossary(&self, request: &CreateGlossaryRequest) -> Result<GlossaryInfo, DeepLError> {
+            let mut glossaries = self.glossaries.lock().unwrap();
+            let glossary_id = format!("{}-{}", request.name, self.created_glossaries.fetch_add(1, Ordering::SeqCst));
+            let glossary = glossary(&glossary_id, &request.name, &request.source_lang, &request.target_lang);
+            glossaries.push(glossary.clone());
+            Ok(glossary)
+        }
+
+        fn delete_glossary(&self, glossary_id: &str) -> Result<(), DeepLError> {
+            self.glossaries.lock().unwrap().retain(|glossary| glossary.glossary_id != glossary_id);
+            Ok(())
+        }
+    }
+
+    fn glossary(glossary_id: &str, name: &str, source_lang: &str, target_lang: &str) -> GlossaryInfo {
+        GlossaryInfo {
+            glossary_id: glossary_id.to_owned(),
+            name: name.to_owned(),
+            ready: true,
+            source_lang: source_lang.to_owned(),
+            target_lang: target_lang.to_owned(),
+            entry_count: 1,
+        }
+    }
+
+    fn plugin(backend: &FakeBackend, entries: &[(&str, quick_search_lib::EntryType)]) -> DeepL {
+        let mut plugin = DeepL::with_backend(PluginId("deepl-test".into()), Logger(None), Arc::new(backend.clone()));
+        plugin.lazy_load_config(config(entries));
+        plugin
+    }
+
+    fn config(entries: &[(&str, quick_search_lib::EntryType)]) -> quick_search_lib::Config {
+        let mut config = default_config();
+        // nothing is coming after the query in a test
+        config.insert("Debounce (ms)".into(), text("0"));
+        for (key, value) in entries {
+            config.insert((*key).into(), value.clone());
+        }
+        config
+    }
+
+    fn text(value: &str) -> quick_search_lib::EntryType {
+        quick_search_lib::EntryType::String { value: value.into() }
+    }
+
+    fn titles(results: RVec<SearchResult>) -> Vec<String> {
+        results.iter().map(|result| result.title().to_owned()).collect()
+    }
+
+    fn with_context(results: RVec<SearchResult>) -> Vec<(String, String)> {
+        results.iter().map(|result| (result.title().to_owned(), result.context().to_owned())).collect()
+    }
+
+    #[test]
+    fn translates_the_text_after_the_header() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(&backend, &[]);
+
+        assert_eq!(titles(plugin.search("en -> de: hello: world".into())), ["[DE] hello: world"]);
+
+        let requests = backend.requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0]["text"], serde_json::json!(["hello: world"]));
+        assert_eq!(requests[0]["target_lang"], "DE");
+        assert_eq!(requests[0]["source_lang"], "EN");
+    }
+
+    #[test]
+    fn leaves_the_source_language_to_deepl_without_an_arrow() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(&backend, &[]);
+
+        assert_eq!(titles(plugin.search("de: hello".into())), ["[DE] hello"]);
+        assert!(backend.requests.lock().unwrap()[0].get("source_lang").is_none());
+    }
+
+    #[test]
+    fn ignores_queries_without_a_header() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(&backend, &[]);
+
+        assert!(plugin.search("hello".into()).is_empty());
+        assert!(plugin.search("xx: hello".into()).is_empty());
+        assert!(backend.requests.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn shows_errors_only_when_asked_to() {
+        let backend = FakeBackend::default();
+        *backend.error.lock().unwrap() = Some(|| DeepLError::QuotaExceeded);
+
+        let quiet = plugin(&backend, &[]);
+        assert!(quiet.search("de: hello".into()).is_empty());
+        assert!(quiet.search("xx: hello".into()).is_empty());
+
+        let verbose = plugin(&backend, &[("Return Error messages", quick_search_lib::EntryType::Bool { value: true })]);
+        assert_eq!(
+            with_context(verbose.search("de: hello".into())),
+            [("Quota exceeded".to_owned(), DeepLError::QuotaExceeded.to_string())]
+        );
+        assert_eq!(
+            with_context(verbose.search("xx: hello".into())),
+            [("Invalid query".to_owned(), "Invalid target language code: xx in »xx«: hello".to_owned())]
+        );
+        assert_eq!(backend.requests.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn translates_into_every_target() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(&backend, &[]);
+
+        assert_eq!(
+            with_context(plugin.search("en -> de, fr,: hello".into())),
+            [("[DE] hello".to_owned(), "German".to_owned()), ("[FR] hello".to_owned(), "French".to_owned())]
+        );
+
+        let requests = backend.requests.lock().unwrap();
+        let targets = requests.iter().map(|request| request["target_lang"].clone()).collect::<Vec<_>>();
+        assert_eq!(targets, ["DE", "FR"]);
+        assert!(requests
+            .iter()
+            .all(|request| request["source_lang"] == "EN" && request["text"] == serde_json::json!(["hello"])));
+    }
+
+    #[test]
+    fn one_invalid_target_fails_the_whole_query() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(&backend, &[]);
+
+        assert!(plugin.search("de,xx: hello".into()).is_empty());
+        assert!(backend.requests.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn plain_text_goes_to_the_default_target() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(&backend, &[("Default target language", text("de"))]);
+
+        assert_eq!(titles(plugin.search("hello".into())), ["[DE] hello"]);
+        assert_eq!(titles(plugin.search("fr: hello".into())), ["[FR] hello"]);
+    }
+
+    #[test]
+    fn trigger_prefix_is_stripped_before_parsing() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(&backend, &[("Default target language", text("de")), ("Trigger prefix", text("tr "))]);
+
+        assert_eq!(titles(plugin.search("tr fr: hello".into())), ["[FR] hello"]);
+        assert_eq!(titles(plugin.search("tr hello".into())), ["[DE] hello"]);
+        assert!(plugin.search("hello".into()).is_empty());
+        assert_eq!(backend.requests.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn serves_repeated_queries_from_the_cache() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(&backend, &[]);
+
+        let first = with_context(plugin.search("de: hello".into()));
+        let second = with_context(plugin.search("de: hello".into()));
+        assert_eq!(first, second);
+        assert_eq!(backend.requests.lock().unwrap().len(), 1);
+
+        plugin.search("fr: hello".into());
+        plugin.search("de: hello again".into());
+        assert_eq!(backend.requests.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn zero_cache_capacity_sends_every_query() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(&backend, &[("Cache capacity", text("0"))]);
+
+        plugin.search("de: hello".into());
+        plugin.search("de: hello".into());
+        assert_eq!(backend.requests.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn goes_offline_when_deepl_cant_be_reached() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(&backend, &[("Return Error messages", quick_search_lib::EntryType::Bool { value: true })]);
+        plugin.search("de: hello".into());
+
+        *backend.error.lock().unwrap() = Some(|| DeepLError::Connection("connection refused".to_owned()));
+        assert_eq!(
+            with_context(plugin.search("fr: hello".into())),
+            [("No connection".to_owned(), DeepLError::Connection("connection refused".to_owned()).to_string())]
+        );
+        assert_eq!(backend.requests.lock().unwrap().len(), 2);
+
+        // offline now, so only the cache answers until the probe finds deepl again
+        assert_eq!(with_context(plugin.search("de: hello".into())), [("[DE] hello".to_owned(), "German (cached)".to_owned())]);
+        assert_eq!(with_context(plugin.search("es: hello".into())), [("Offline".to_owned(), DeepLError::Offline.to_string())]);
+        assert_eq!(backend.requests.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn serves_expired_translations_when_deepl_cant_be_reached() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(&backend, &[("Return Error messages", quick_search_lib::EntryType::Bool { value: true })]);
+        plugin.search("de: hello".into());
+
+        plugin.cache.lock().unwrap().resize(DEFAULT_CACHE_CAPACITY, std::time::Duration::from_millis(1));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        *backend.error.lock().unwrap() = Some(|| DeepLError::Connection("connection refused".to_owned()));
+
+        assert_eq!(with_context(plugin.search("de: hello".into())), [("[DE] hello".to_owned(), "German (cached)".to_owned())]);
+        assert_eq!(backend.requests.lock().unwrap().len(), 2);
+
+        // offline now, so nothing new is sent until the probe finds deepl again
+        assert_eq!(with_context(plugin.search("fr: hello".into())), [("Offline".to_owned(), DeepLError::Offline.to_string())]);
+        assert_eq!(backend.requests.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn offline_mode_never_calls_the_backend() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(
+            &backend,
+            &[
+                ("Offline mode", quick_search_lib::EntryType::Bool { value: true }),
+                ("Return Error messages", quick_search_lib::EntryType::Bool { value: true }),
+            ],
+        );
+
+        assert_eq!(with_context(plugin.search("de: hello".into())), [("Offline".to_owned(), DeepLError::Offline.to_string())]);
+        assert!(backend.requests.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn drops_searches_superseded_while_debouncing() {
+        let backend = FakeBackend::default();
+        let plugin = Arc::new(plugin(&backend, &[("Debounce (ms)", text("200"))]));
+
+        let first = std::thread::spawn({
+            let plugin = plugin.clone();
+            move || plugin.search("de: hel".into())
+        });
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        let second = std::thread::spawn({
+            let plugin = plugin.clone();
+            move || plugin.search("de: hello".into())
+        });
+
+        assert!(first.join().unwrap().is_empty());
+        assert_eq!(titles(second.join().unwrap()), ["[DE] hello"]);
+
+        let requests = backend.requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0]["text"], serde_json::json!(["hello"]));
+    }
+
+    #[test]
+    fn default_formality_only_goes_where_it_is_supported() {
+        let backend = FakeBackend::default();
+        let less = plugin(&backend, &[("Default formality", text("less"))]);
+        less.search("de,zh: hello".into());
+        let prefer = plugin(&backend, &[("Default formality", text("prefer_more"))]);
+        prefer.search("de,zh: hello".into());
+
+        let formalities = backend.requests.lock().unwrap().iter().map(|request| request.get("formality").cloned()).collect::<Vec<_>>();
+        assert_eq!(formalities, [Some("less".into()), None, Some("prefer_more".into()), Some("prefer_more".into())]);
+    }
+
+    #[test]
+    fn invalid_default_formality_is_ignored() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(&backend, &[("Default formality", text("bogus"))]);
+
+        assert_eq!(titles(plugin.search("de: hello".into())), ["[DE] hello"]);
+        assert!(backend.requests.lock().unwrap()[0].get("formality").is_none());
+    }
+
+    #[test]
+    fn formality_modifier_applies_to_its_target() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(
+            &backend,
+            &[
+                ("Default formality", text("less")),
+                ("Return Error messages", quick_search_lib::EntryType::Bool { value: true }),
+            ],
+        );
+
+        plugin.search("de!formal, fr: hello".into());
+        let formalities = backend.requests.lock().unwrap().iter().map(|request| request["formality"].clone()).collect::<Vec<_>>();
+        assert_eq!(formalities, ["more", "less"]);
+
+        assert_eq!(
+            with_context(plugin.search("zh!more: hello".into())),
+            [(
+                "Formality not supported".to_owned(),
+                format!("{} in zh!»more«: hello", DeepLError::FormalityNotSupported(TargetLanguageCode::ZH))
+            )]
+        );
+        assert_eq!(titles(plugin.search("de!polite: hello".into())), ["Invalid query"]);
+    }
+
+    #[test]
+    fn both_shows_a_formal_and_an_informal_translation() {
+        let backend = FakeBackend::default();
+        let both = plugin(&backend, &[]);
+
+        assert_eq!(
+            with_context(both.search("de!both, fr: hello".into())),
+            [
+                ("[DE] hello".to_owned(), "German (formal)".to_owned()),
+                ("[DE] hello".to_owned(), "German (informal)".to_owned()),
+                ("[FR] hello".to_owned(), "French".to_owned()),
+            ]
+        );
+
+        let variants = plugin(&backend, &[("Show formal and informal variants", quick_search_lib::EntryType::Bool { value: true })]);
+        assert_eq!(
+            with_context(variants.search("es, zh: hello".into())),
+            [
+                ("[ES] hello".to_owned(), "Spanish (formal)".to_owned()),
+                ("[ES] hello".to_owned(), "Spanish (informal)".to_owned()),
+                ("[ZH] hello".to_owned(), "Chinese".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn attaches_the_glossary_for_the_language_pair() {
+        let backend = FakeBackend::default();
+        backend.glossaries.lock().unwrap().push(glossary("en-de-id", "product", "en", "de"));
+        let plugin = plugin(&backend, &[]);
+
+        plugin.search("en -> de, fr: hello".into());
+        // deepl only uses a glossary when the source language is given
+        plugin.search("de: hello".into());
+
+        let glossary_ids = backend
+            .requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|request| request.get("glossary_id").cloned())
+            .collect::<Vec<_>>();
+        assert_eq!(glossary_ids, [Some("en-de-id".into()), None, None]);
+    }
+
+    #[test]
+    fn creates_lists_and_deletes_glossaries() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(&backend, &[]);
+
+        let path = std::env::temp_dir().join(format!("deepl-glossary-{}", std::process::id())).join("en-de.tsv");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "hello\thallo\r\n\nworld\twelt\n").unwrap();
+
+        let create = plugin.search(format!("glossary create: {}", path.display()).into());
+        assert_eq!(
+            with_context(create.clone()),
+            [("Create glossary en-de".to_owned(), format!("EN -> DE, 2 entries from {}", path.display()))]
+        );
+        plugin.execute(&create[0]);
+        assert_eq!(with_context(plugin.search("glossaries:".into())), [("en-de".to_owned(), "EN -> DE, 1 entries".to_owned())]);
+
+        let delete = plugin.search("glossary delete: en-de".into());
+        assert_eq!(titles(delete.clone()), ["Delete glossary en-de"]);
+        plugin.execute(&delete[0]);
+        assert!(plugin.search("glossaries:".into()).is_empty());
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn uploads_the_glossary_directory() {
+        let dir = std::env::temp_dir().join(format!("deepl-glossary-dir-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("en-de.tsv"), "hello\thallo\n").unwrap();
+        std::fs::write(dir.join("german.tsv"), "hello\thallo\n").unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a term file").unwrap();
+
+        let backend = FakeBackend::default();
+        backend.glossaries.lock().unwrap().push(glossary("other", "other", "en", "de"));
+        let plugin = plugin(&backend, &[("Glossary directory", text(&dir.display().to_string()))]);
+
+        let names = backend.glossaries.lock().unwrap().iter().map(|glossary| glossary.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names, ["other", "en-de"]);
+
+        // the synced glossary wins over the account's other glossary for the same pair
+        plugin.search("en -> de: hello".into());
+        assert_eq!(backend.requests.lock().unwrap()[0]["glossary_id"], "en-de-0");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    fn warns_about_usage(threshold: &str, count: u64, limit: u64) -> bool {
+        let backend = FakeBackend::default();
+        *backend.usage.lock().unwrap() = Some(serde_json::json!({ "character_count": count, "character_limit": limit }));
+        let plugin = plugin(&backend, &[("Usage warning threshold (%)", text(threshold))]);
+        titles(plugin.search("?usage".into())).contains(&"Usage warning".to_owned())
+    }
+
+    #[test]
+    fn usage_shows_characters_and_documents() {
+        let backend = FakeBackend::default();
+        *backend.usage.lock().unwrap() = Some(serde_json::json!({
+            "character_count": 180118,
+            "character_limit": 1250000,
+            "document_count": 3,
+            "document_limit": 10
+        }));
+        let plugin = plugin(&backend, &[]);
+
+        let results = with_context(plugin.search("deepl: usage".into()));
+        assert!(results.contains(&(
+            "180,118 / 1,250,000 characters".to_owned(),
+            "14.4% of the character limit used this billing period".to_owned()
+        )));
+        assert!(results.contains(&("3 / 10 documents".to_owned(), "30.0% of the document limit used this billing period".to_owned())));
+        assert!(!results.iter().any(|(title, _)| title == "Usage warning"));
+    }
+
+    #[test]
+    fn usage_warns_from_the_threshold_on() {
+        assert!(!warns_about_usage("80", 799, 1000));
+        assert!(warns_about_usage("80", 800, 1000));
+        // 0 turns the warning off, even with the whole quota used
+        assert!(!warns_about_usage("0", 1000, 1000));
+        // an account without a character limit has used nothing
+        assert!(!warns_about_usage("80", 5, 0));
+    }
+
+    #[test]
+    fn refuses_translations_over_the_monthly_budget() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(&backend, &[("Monthly character budget", text("10"))]);
+
+        // hello is 5 characters, so the second one still fits and the third doesn't
+        assert_eq!(titles(plugin.search("en -> de: hello".into())), ["[DE] hello"]);
+        assert_eq!(titles(plugin.search("en -> fr: hello".into())), ["[FR] hello"]);
+        // shown even though "Return Error messages" is off
+        assert_eq!(titles(plugin.search("en -> it: hello".into())), ["Character budget reached"]);
+        assert_eq!(backend.requests.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn failed_requests_dont_count_against_the_budget() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(&backend, &[("Monthly character budget", text("5"))]);
+
+        *backend.error.lock().unwrap() = Some(|| DeepLError::Request("nope".to_owned()));
+        plugin.search("en -> de: hello".into());
+
+        *backend.error.lock().unwrap() = None;
+        assert_eq!(titles(plugin.search("en -> de: hello".into())), ["[DE] hello"]);
+    }
+
+    #[test]
+    fn loading_the_config_doesnt_wait_for_the_language_list() {
+        let backend = FakeBackend::default();
+        *backend.languages_delay.lock().unwrap() = std::time::Duration::from_secs(2);
+
+        let started = std::time::Instant::now();
+        let plugin = plugin(&backend, &[]);
+        assert_eq!(titles(plugin.search("de: hello".into())), ["[DE] hello"]);
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn misspelled_languages_are_still_understood() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(&backend, &[]);
+
+        assert_eq!(titles(plugin.search("englsh -> germ: hello".into())), ["[DE] hello"]);
+        assert_eq!(backend.requests.lock().unwrap()[0]["source_lang"], "EN");
+    }
+
+    #[test]
+    fn near_misses_are_only_suggested_when_errors_are_shown() {
+        let backend = FakeBackend::default();
+        let quiet = plugin(&backend, &[]);
+        assert!(quiet.search("check: this".into()).is_empty());
+        assert!(quiet.search("tip: x".into()).is_empty());
+
+        let verbose = plugin(&backend, &[("Return Error messages", quick_search_lib::EntryType::Bool { value: true })]);
+        assert_eq!(titles(verbose.search("check: this".into())), ["Czech"]);
+        assert!(backend.requests.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn ambiguous_languages_are_always_answered_with_candidates() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(&backend, &[]);
+
+        assert_eq!(
+            with_context(plugin.search("slo: hello".into())),
+            [
+                ("Slovak".to_owned(), "Did you mean Slovak for \"slo\"? Use sk: instead".to_owned()),
+                ("Slovenian".to_owned(), "Did you mean Slovenian for \"slo\"? Use sl: instead".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn options_end_up_in_the_request() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(&backend, &[]);
+
+        plugin.search("de [nosplit, context=\"a river\"] +html: <b>bank</b>".into());
+        let request = backend.requests.lock().unwrap()[0].clone();
+        assert_eq!(request["split_sentences"], "0");
+        assert_eq!(request["tag_handling"], "html");
+        assert_eq!(request["context"], "a river");
+        assert_eq!(request["text"][0], "<b>bank</b>");
+    }
+
+    #[test]
+    fn text_in_the_primary_language_goes_to_the_secondary_one() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(&backend, &[("My languages", text("EN-US, DE"))]);
+
+        assert_eq!(titles(plugin.search("hello".into())), ["[DE] hello"]);
+
+        let requests = backend.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0]["target_lang"], "EN-US");
+        assert_eq!(requests[1]["target_lang"], "DE");
+        assert_eq!(requests[1]["source_lang"], "EN");
+    }
+
+    #[test]
+    fn text_in_other_languages_goes_to_the_primary_one() {
+        let backend = FakeBackend::default();
+        *backend.detected.lock().unwrap() = Some("FR");
+        let plugin = plugin(&backend, &[("My languages", text("EN-US, DE"))]);
+
+        assert_eq!(titles(plugin.search("bonjour".into())), ["[EN-US] bonjour"]);
+        assert_eq!(backend.requests.lock().unwrap().len(), 1);
+
+        // a query with its own header is left alone
+        assert_eq!(titles(plugin.search("en-us: hello".into())), ["[EN-US] hello"]);
+    }
+
+    #[test]
+    fn broken_my_languages_only_stop_queries_without_a_header() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(
+            &backend,
+            &[
+                ("My languages", text("EN-US")),
+                ("Return Error messages", quick_search_lib::EntryType::Bool { value: true }),
+            ],
+        );
+
+        assert_eq!(titles(plugin.search("de: hello".into())), ["[DE] hello"]);
+        assert_eq!(
+            with_context(plugin.search("hello".into())),
+            [("Invalid config".to_owned(), DeepLError::InvalidMyLanguages("EN-US".to_owned()).to_string())]
+        );
+        assert_eq!(backend.requests.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn back_translations_go_right_below_their_translations() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(&backend, &[("My languages", text("EN-GB, JA"))]);
+
+        assert_eq!(
+            titles(plugin.search("de!both, fr +back: hello".into())),
+            ["[DE] hello", "[EN-GB] [DE] hello", "[DE] hello", "[EN-GB] [DE] hello", "[FR] hello", "[EN-GB] [FR] hello",]
+        );
+
+        let requests = backend.requests.lock().unwrap();
+        let back = requests.iter().find(|request| request["text"][0] == "[FR] hello").unwrap();
+        assert_eq!(back["source_lang"], "FR");
+        assert_eq!(back["target_lang"], "EN-GB");
+    }
+
+    #[test]
+    fn back_translations_without_my_languages_go_into_a_concrete_variant() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(&backend, &[]);
+
+        assert_eq!(titles(plugin.search("de +back: hello".into())), ["[DE] hello", "[EN-US] [DE] hello"]);
+
+        let requests = backend.requests.lock().unwrap();
+        assert_eq!(requests[1]["source_lang"], "DE");
+        assert_eq!(requests[1]["target_lang"], "EN-US");
+    }
+
+    #[test]
+    fn back_translations_can_be_turned_on_for_every_query() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(
+            &backend,
+            &[
+                ("My languages", text("EN-US, DE")),
+                ("Show back-translations", quick_search_lib::EntryType::Bool { value: true }),
+            ],
+        );
+
+        assert_eq!(
+            with_context(plugin.search("fr: hello".into())),
+            [
+                ("[FR] hello".to_owned(), "French".to_owned()),
+                ("[EN-US] [FR] hello".to_owned(), "Back-translation into English (American)".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn named_glossary_brings_its_source_language() {
+        let backend = FakeBackend::default();
+        backend.glossaries.lock().unwrap().push(glossary("product-en-de", "product", "en", "de"));
+        let plugin = plugin(&backend, &[("Return Error messages", quick_search_lib::EntryType::Bool { value: true })]);
+
+        assert_eq!(
+            with_context(plugin.search("de [formal, glossary=product, nosplit]: text".into())),
+            [("[DE] text".to_owned(), "German (formal)".to_owned())]
+        );
+        let request = backend.requests.lock().unwrap().pop().unwrap();
+        assert_eq!(request["source_lang"], "EN");
+        assert_eq!(request["glossary_id"], "product-en-de");
+        assert_eq!(request["formality"], "more");
+        assert_eq!(request["split_sentences"], "0");
+
+        assert_eq!(
+            with_context(plugin.search("fr [glossary=product]: text".into())),
+            [("Glossary".to_owned(), "Glossary product isn't for FR".to_owned())]
+        );
+    }
+
+    #[test]
+    fn trigger_prefix_is_only_needed_without_a_header() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(&backend, &[("Trigger prefix", text("tr ")), ("Default target language", text("de"))]);
+
+        assert!(plugin.search("hello".into()).is_empty());
+        assert_eq!(titles(plugin.search("tr hello".into())), ["[DE] hello"]);
+        assert_eq!(titles(plugin.search("fr: hello".into())), ["[FR] hello"]);
+        assert_eq!(titles(plugin.search("en -> ja: hello".into())), ["[JA] hello"]);
+        assert_eq!(titles(plugin.search("tr fr: hello".into())), ["[FR] hello"]);
+        // the last one is the same translation as fr: hello, so it comes from the cache
+        assert_eq!(backend.requests.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn always_shows_bad_options() {
+        let backend = FakeBackend::default();
+        let plugin = plugin(&backend, &[]);
+
+        for query in ["de +htlm: x", "de [split=7]: x", "de [formal: x"] {
+            assert_eq!(titles(plugin.search(query.into())), ["Invalid option"], "{}", query);
         }
+        assert!(backend.requests.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn forgets_synced_glossaries_that_were_deleted() {
+        let dir = std::env::temp_dir().join(format!("deepl-plugin-glossaries-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("en-de.tsv"), "Hello\tServus\n").unwrap();
+
+        let backend = FakeBackend::default();
+        let entries = [("Glossary directory", text(&dir.display().to_string()))];
+        let mut plugin = plugin(&backend, &entries);
+        let last_glossary_id = |backend: &FakeBackend| backend.requests.lock().unwrap().last().and_then(|request| request.get("glossary_id").cloned());
+
+        plugin.search("en -> de: one".into());
+        assert_eq!(last_glossary_id(&backend), Some("en-de-0".into()));
+
+        // nothing changed, so a reload keeps using the same glossary
+        plugin.lazy_load_config(config(&entries));
+        assert_eq!(backend.glossaries.lock().unwrap().len(), 1);
+
+        // deleted elsewhere, e.g. in the DeepL UI, the next sync uploads the file again
+        backend.glossaries.lock().unwrap().clear();
+        plugin.lazy_load_config(config(&entries));
+        plugin.search("en -> de: two".into());
+        assert_eq!(last_glossary_id(&backend), Some("en-de-1".into()));
+
+        // deleted through the plugin, it isn't attached anymore
+        plugin.delete_glossary("en-de-1").unwrap();
+        plugin.search("en -> de: three".into());
+        assert_eq!(last_glossary_id(&backend), None);
+
+        let _ = std::fs::remove_dir_all(&dir);
     }
 }
@@ -0,0 +1,617 @@
+use std::ops::Range;
+
+use crate::{
+    backend::{Formality, SplitSentences, TagHandling},
+    error::DeepLError,
+    SourceLanguageCode, TargetLanguageCode,
+};
+
+// the query grammar:
+// query    := [header ':'] text
+// header   := [source '->'] targets options*
+// targets  := target (',' target)*
+// target   := language ['!' modifier]
+// modifier := a formality (formal, less, prefer_more, ...) or both
+// options  := '[' flag (',' flag)* ']' | '+' flag
+// flag     := name ['=' value], see apply_flag for the names
+//
+// a backslash escapes the next character in the header, so `\:` and `\->` are literal, and double quotes keep a flag value together, e.g. [context="a, b: c"]
+// the text is everything after the first unescaped colon, trimmed; `\:` in it is a literal colon, so `note\: milk` isn't read as a header
+// text in double quotes is taken as is (apart from \" and \\), spaces and colons included, e.g. de: "  note: milk  "
+// the text can end with `|| context: <text>`, which is sent as context instead of being translated, e.g. de: bank || context: river side
+
+#[derive(Debug)]
+pub struct ParsedQuery {
+    pub source: Option<SourceLanguageCode>,
+    pub targets: Vec<Target>,
+    pub options: QueryOptions,
+    pub text: String,
+    // the query had no header of its own, so it's translated as the default header says
+    pub uses_default: bool,
+}
+
+// flags that apply to every target, a formality flag ends up in the targets' modifiers instead
+#[derive(Debug, Default)]
+pub struct QueryOptions {
+    pub glossary: Option<GlossaryOption>,
+    pub split_sentences: Option<SplitSentences>,
+    pub preserve_formatting: Option<bool>,
+    pub tag_handling: Option<TagHandling>,
+    pub context: Option<String>,
+    // also translate every translation back into the language of the query
+    pub back_translate: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GlossaryOption {
+    // a glossary name or id
+    Named(String),
+    // no glossary, not even the one that would be attached automatically
+    Off,
+}
+
+#[derive(Debug)]
+pub struct Target {
+    pub language: TargetLanguageCode,
+    pub modifier: Option<Modifier>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Modifier {
+    Formality(Formality),
+    // one translation per formality
+    Both,
+}
+
+// None when the query isn't meant for us: it's empty, or has no header and there's no default header to fall back to
+// `default_header` is used for queries without a header, it has the same syntax, e.g. "de" or "en -> de,fr"
+// a header that doesn't parse is treated as part of the text when there is a default header, so "note: milk" still gets translated
+pub fn parse(query: &str, default_header: &str) -> Result<Option<ParsedQuery>, DeepLError> {
+    if query.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let default_header = default_header.trim();
+
+    let (header, text) = match find_header_end(query) {
+        Some(end) => (&query[..end], &query[end + 1..]),
+        None if default_header.is_empty() => return Ok(None),
+        None => return parse_with_default(query, default_header).map(Some),
+    };
+
+    if header.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let (source, targets, options) = match parse_header(header) {
+        Ok(parsed) => parsed,
+        // only a header that isn't a language at all is taken for text, a typo in a real one should still be pointed out
+        // a header that merely comes close to some languages is text too, "tip: ..." is no more a misspelled "jp" than it's a language
+        Err((DeepLError::InvalidSourceLanguage(_) | DeepLError::InvalidTargetLanguage(_) | DeepLError::AmbiguousLanguage(_, _) | DeepLError::MisspelledLanguage(_, _), _))
+            if !default_header.is_empty() =>
+        {
+            return parse_with_default(query, default_header).map(Some)
+        }
+        Err((e, span)) => return Err(DeepLError::InQuery(Box::new(e), mark(query, span))),
+    };
+
+    let (text, context) = split_context(text);
+    let text = parse_text(text);
+    if text.is_empty() {
+        return Err(DeepLError::NoQuery);
+    }
+
+    let mut options = options;
+    if context.is_some() {
+        options.context = context;
+    }
+
+    Ok(Some(ParsedQuery {
+        source,
+        targets,
+        options,
+        text,
+        uses_default: false,
+    }))
+}
+
+// the whole query is text, translated as the default header says
+fn parse_with_default(query: &str, default_header: &str) -> Result<ParsedQuery, DeepLError> {
+    let (source, targets, mut options) =
+        parse_header(default_header).map_err(|(e, span)| DeepLError::InQuery(Box::new(e), format!("the default target language {}", mark(default_header, span))))?;
+
+    let (text, context) = split_context(query);
+    if context.is_some() {
+        options.context = context;
+    }
+
+    Ok(ParsedQuery {
+        source,
+        targets,
+        options,
+        text: parse_text(text),
+        uses_default: true,
+    })
+}
+
+// the first colon that isn't escaped, unless the query starts with a quote and so is all text
+fn find_header_end(query: &str) -> Option<usize> {
+    if query.trim_start().starts_with('"') {
+        return None;
+    }
+
+    split_unescaped(query, ":").first().map(|(start, part)| start + part.len()).filter(|end| *end < query.len())
+}
+
+type HeaderError = (DeepLError, Range<usize>);
+
+// a formality flag with where it is, so an error about it can point at it
+type FormalityFlag = (Modifier, Range<usize>);
+
+fn parse_header(header: &str) -> Result<(Option<SourceLanguageCode>, Vec<Target>, QueryOptions), HeaderError> {
+    // languages never contain [ or +, so the options start at the first of them
+    let options_start = active_chars(header)
+        .into_iter()
+        .find(|(_, c)| *c == '[' || *c == '+')
+        .map(|(i, _)| i)
+        .unwrap_or(header.len());
+    let (languages, options) = header.split_at(options_start);
+
+    let parts = split_unescaped(languages, "->");
+    let (source, targets) = match parts.as_slice() {
+        [targets] => (None, *targets),
+        [source, targets] => (Some(*source), *targets),
+        [_, _, (start, _), ..] => return Err((DeepLError::TooManyArrows, start - 2..*start)),
+        [] => return Err((DeepLError::NoTargetLanguage, 0..languages.len())),
+    };
+
+    let source = match source {
+        // an arrow with nothing in front of it, point at the arrow
+        Some((start, source)) if unescape(source).trim().is_empty() => {
+            let arrow = start + source.len();
+            return Err((DeepLError::InvalidSourceLanguage(String::new()), arrow..arrow + 2));
+        }
+        Some((start, source)) => Some(parse_language::<SourceLanguageCode>(start, source)?),
+        None => None,
+    };
+
+    // the languages come first, so a header that isn't one is still told apart from one with a bad option
+    let mut languages_with_modifiers = vec![];
+    for (start, target) in split_unescaped(targets.1, ",").into_iter().map(|(start, target)| (targets.0 + start, target)) {
+        if target.trim().is_empty() {
+            continue;
+        }
+
+        let (language, modifier) = match split_unescaped(target, "!").as_slice() {
+            [(_, language)] => (*language, None),
+            [(_, language), (modifier_start, modifier)] => (*language, Some((start + modifier_start, *modifier))),
+            [_, _, (extra, _), ..] => return Err((DeepLError::InvalidFormality(unescape(&target[extra - 1..])), start + extra - 1..start + target.len())),
+            [] => continue,
+        };
+
+        languages_with_modifiers.push((parse_language::<TargetLanguageCode>(start, language)?, modifier));
+    }
+
+    if languages_with_modifiers.is_empty() {
+        return Err((DeepLError::NoTargetLanguage, 0..languages.len()));
+    }
+
+    let (options, formality) = parse_options(options_start, options)?;
+
+    let mut parsed = vec![];
+    for (language, modifier) in languages_with_modifiers {
+        let modifier = match (modifier, &formality) {
+            // a ! with nothing after it, point at the !
+            (Some((modifier_start, modifier)), _) if unescape(modifier).trim().is_empty() => {
+                return Err((DeepLError::InvalidFormality(String::new()), modifier_start - 1..modifier_start))
+            }
+            (Some((modifier_start, modifier)), _) => {
+                let span = trimmed_span(modifier_start, modifier);
+                Some(check_modifier(&language, parse_modifier(&unescape(modifier), span.clone())?, span)?)
+            }
+            // a target's own modifier wins over a formality flag for the whole query
+            (None, Some((modifier, span))) => Some(check_modifier(&language, *modifier, span.clone())?),
+            (None, None) => None,
+        };
+
+        parsed.push(Target { language, modifier });
+    }
+
+    Ok((source, parsed, options))
+}
+
+// the [...] groups and +flags after the targets, `offset` is where they start in the header
+fn parse_options(offset: usize, s: &str) -> Result<(QueryOptions, Option<FormalityFlag>), HeaderError> {
+    let mut options = QueryOptions::default();
+    let mut formality = None;
+
+    let active = active_chars(s);
+    let mut i = 0;
+    while i < s.len() {
+        let rest = &s[i..];
+        let c = rest.chars().next().unwrap_or_default();
+
+        if c.is_whitespace() {
+            i += c.len_utf8();
+            continue;
+        }
+
+        let (flags, end) = match c {
+            '[' => {
+                let close = active.iter().find(|(j, c)| *j > i && *c == ']').map(|(j, _)| *j);
+                let close = close.ok_or((DeepLError::UnclosedOptions, offset + i..offset + s.len()))?;
+                let flags = split_unescaped(&s[i + 1..close], ",")
+                    .into_iter()
+                    .map(|(start, flag)| (i + 1 + start, flag))
+                    .collect::<Vec<_>>();
+                (flags, close + 1)
+            }
+            '+' => {
+                // a +flag ends at the next space that isn't quoted, or where the next group starts
+                let end = active
+                    .iter()
+                    .find(|(j, c)| *j > i && (c.is_whitespace() || *c == '[' || *c == '+'))
+                    .map(|(j, _)| *j)
+                    .unwrap_or(s.len());
+                (vec![(i + 1, &s[i + 1..end])], end)
+            }
+            _ => {
+                let end = rest.find(char::is_whitespace).map(|end| i + end).unwrap_or(s.len());
+                return Err((DeepLError::UnknownFlag(unescape(&s[i..end])), offset + i..offset + end));
+            }
+        };
+
+        for (start, flag) in flags {
+            apply_flag(&mut options, &mut formality, offset + start, flag)?;
+        }
+        i = end;
+    }
+
+    Ok((options, formality))
+}
+
+// flag names are case insensitive, values are taken as they are
+fn apply_flag(options: &mut QueryOptions, formality: &mut Option<FormalityFlag>, start: usize, flag: &str) -> Result<(), HeaderError> {
+    let span = trimmed_span(start, flag);
+    let (name, value) = match split_unescaped(flag, "=").as_slice() {
+        [(_, name)] => (unescape(name).trim().to_lowercase(), None),
+        [(_, name), (value_start, _), ..] => (unescape(name).trim().to_lowercase(), Some(parse_text(&flag[*value_start..]))),
+        [] => return Ok(()),
+    };
+
+    let invalid = |value: &str| Err((DeepLError::InvalidFlagValue(name.clone(), value.to_owned()), span.clone()));
+
+    match (name.as_str(), value.as_deref()) {
+        // [de, fr,] is fine
+        ("", None) => {}
+        ("glossary", Some("none" | "off")) | ("noglossary", None) => options.glossary = Some(GlossaryOption::Off),
+        ("glossary", Some("")) => return invalid(""),
+        ("glossary", Some(glossary)) => options.glossary = Some(GlossaryOption::Named(glossary.to_owned())),
+        ("nosplit", None) => options.split_sentences = Some(SplitSentences::Off),
+        ("split", None) => options.split_sentences = Some(SplitSentences::On),
+        ("split", Some(split)) => {
+            options.split_sentences = Some(match split.to_lowercase().as_str() {
+                "0" | "off" | "no" => SplitSentences::Off,
+                "1" | "on" | "yes" => SplitSentences::On,
+                "nonewlines" => SplitSentences::NoNewlines,
+                _ => return invalid(split),
+            })
+        }
+        ("preserve" | "preserve_formatting", None) => options.preserve_formatting = Some(true),
+        ("html", None) => options.tag_handling = Some(TagHandling::Html),
+        ("xml", None) => options.tag_handling = Some(TagHandling::Xml),
+        ("tags" | "tag_handling", Some(tags)) => {
+            options.tag_handling = Some(match tags.to_lowercase().as_str() {
+                "html" => TagHandling::Html,
+                "xml" => TagHandling::Xml,
+                _ => return invalid(tags),
+            })
+        }
+        ("back", None) => options.back_translate = true,
+        ("context", Some(context)) => options.context = Some(context.to_owned()).filter(|context| !context.is_empty()),
+        ("formality", Some(value)) => *formality = Some((parse_modifier(value, span.clone())?, span)),
+        // a known flag with a value it doesn't take, or without the value it needs
+        ("glossary" | "noglossary" | "nosplit" | "preserve" | "preserve_formatting" | "html" | "xml" | "tags" | "tag_handling" | "back" | "context" | "formality", value) => {
+            return invalid(value.unwrap_or_default())
+        }
+        // formal, informal, prefer_more, both, ... on their own
+        (name, None) => match parse_modifier(name, span.clone()) {
+            Ok(modifier) => *formality = Some((modifier, span)),
+            Err(_) => return Err((DeepLError::UnknownFlag(name.to_owned()), span)),
+        },
+        (name, Some(_)) => return Err((DeepLError::UnknownFlag(name.to_owned()), span)),
+    }
+
+    Ok(())
+}
+
+fn parse_language<T: std::str::FromStr<Err = DeepLError>>(start: usize, language: &str) -> Result<T, HeaderError> {
+    let span = trimmed_span(start, language);
+    unescape(language).trim().to_lowercase().parse::<T>().map_err(|e| (e, span))
+}
+
+fn parse_modifier(modifier: &str, span: Range<usize>) -> Result<Modifier, HeaderError> {
+    let modifier = modifier.trim().to_lowercase();
+
+    match modifier.as_str() {
+        "both" => Ok(Modifier::Both),
+        _ => match Formality::guess_from_str(&modifier) {
+            Some(formality) => Ok(Modifier::Formality(formality)),
+            None => Err((DeepLError::InvalidFormality(modifier), span)),
+        },
+    }
+}
+
+fn check_modifier(language: &TargetLanguageCode, modifier: Modifier, span: Range<usize>) -> Result<Modifier, HeaderError> {
+    // asking for a formality the language doesn't have is most likely a mistake, so say so instead of quietly ignoring it
+    let strict = match modifier {
+        Modifier::Both => true,
+        Modifier::Formality(formality) => formality.is_strict(),
+    };
+    if strict && !language.supports_formality() {
+        return Err((DeepLError::FormalityNotSupported(language.clone()), span));
+    }
+
+    Ok(modifier)
+}
+
+fn parse_text(text: &str) -> String {
+    let text = text.trim();
+
+    match text.strip_prefix('"').and_then(|text| text.strip_suffix('"')) {
+        Some(quoted) => {
+            let mut unquoted = String::new();
+            let mut chars = quoted.chars();
+            while let Some(c) = chars.next() {
+                match (c, chars.clone().next()) {
+                    ('\\', Some(next @ ('"' | '\\'))) => {
+                        unquoted.push(next);
+                        chars.next();
+                    }
+                    _ => unquoted.push(c),
+                }
+            }
+            unquoted
+        }
+        None => text.replace("\\:", ":"),
+    }
+}
+
+// the text before the first unquoted `|| context:` and the context after it, a `||` followed by anything else is part of the text
+fn split_context(text: &str) -> (&str, Option<String>) {
+    for (i, _) in active_chars(text) {
+        if !text[i..].starts_with("||") {
+            continue;
+        }
+
+        let rest = text[i + 2..].trim_start();
+        if let Some(context) = rest.get(..8).filter(|prefix| prefix.eq_ignore_ascii_case("context:")).map(|_| parse_text(&rest[8..])) {
+            return (&text[..i], Some(context).filter(|context| !context.is_empty()));
+        }
+    }
+
+    (text, None)
+}
+
+// splits on every occurrence of `separator` that isn't escaped or quoted, giving each part with its byte offset in `s`
+fn split_unescaped<'a>(s: &'a str, separator: &str) -> Vec<(usize, &'a str)> {
+    let mut parts = vec![];
+    let mut start = 0;
+
+    for (i, _) in active_chars(s) {
+        if i >= start && s[i..].starts_with(separator) {
+            parts.push((start, &s[start..i]));
+            start = i + separator.len();
+        }
+    }
+
+    parts.push((start, &s[start..]));
+    parts
+}
+
+// the characters that mean something, everything but escaped characters and the contents of double quotes
+fn active_chars(s: &str) -> Vec<(usize, char)> {
+    let mut active = vec![];
+    let mut escaped = false;
+    let mut quoted = false;
+
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            _ if !quoted => active.push((i, c)),
+            _ => {}
+        }
+    }
+
+    active
+}
+
+// drops the backslash in front of every escaped character
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+// the span of `part` (which starts at `start`) without the spaces around it, an empty span at `start` if it's all spaces
+fn trimmed_span(start: usize, part: &str) -> Range<usize> {
+    if part.trim().is_empty() {
+        return start..start;
+    }
+
+    let leading = part.len() - part.trim_start().len();
+    start + leading..start + part.trim_end().len()
+}
+
+// points out the part of the query an error is about, e.g. de,»xx«: hello
+fn mark(query: &str, span: Range<usize>) -> String {
+    format!("{}»{}«{}", &query[..span.start], &query[span.clone()], &query[span.end..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(query: &str, default_header: &str) -> String {
+        parse(query, default_header).expect_err("the query should be rejected").to_string()
+    }
+
+    fn parsed(query: &str, default_header: &str) -> ParsedQuery {
+        parse(query, default_header).expect("the query should parse").expect("the query should be translated")
+    }
+
+    fn languages(query: &ParsedQuery) -> Vec<TargetLanguageCode> {
+        query.targets.iter().map(|target| target.language.clone()).collect()
+    }
+
+    #[test]
+    fn header_and_text() {
+        let query = parsed("en -> de, fr!less: hello world ", "");
+        assert_eq!(query.source, Some(SourceLanguageCode::EN));
+        assert_eq!(languages(&query), vec![TargetLanguageCode::DE, TargetLanguageCode::FR]);
+        assert_eq!(query.targets[1].modifier, Some(Modifier::Formality(Formality::Less)));
+        assert_eq!(query.text, "hello world");
+        assert!(!query.uses_default);
+    }
+
+    #[test]
+    fn not_for_us() {
+        assert!(parse("", "de").unwrap().is_none());
+        assert!(parse("hello", "").unwrap().is_none());
+        assert!(parse("  : hello", "").unwrap().is_none());
+        assert!(matches!(parse("de:  ", ""), Err(DeepLError::NoQuery)));
+    }
+
+    #[test]
+    fn quoted_text() {
+        assert_eq!(parsed("de: \"  note: milk  \"", "").text, "  note: milk  ");
+        assert_eq!(parsed("de: \"say \\\"hi\\\" \\\\ bye\"", "").text, "say \"hi\" \\ bye");
+        // a query that starts with a quote has no header at all
+        let query = parsed("\"fr: x\"", "de");
+        assert_eq!(languages(&query), vec![TargetLanguageCode::DE]);
+        assert_eq!(query.text, "fr: x");
+    }
+
+    #[test]
+    fn escapes() {
+        let query = parsed("note\\: milk", "de");
+        assert_eq!(query.text, "note: milk");
+        assert!(query.uses_default);
+
+        assert_eq!(parsed("de: a\\:b", "").text, "a:b");
+        assert_eq!(parsed("de: a:b", "").text, "a:b");
+
+        // an escaped arrow isn't an arrow, so the header is one (invalid) target
+        assert_eq!(error("en \\-> de: x", ""), "Invalid target language code: en -> de in »en \\-> de«: x");
+    }
+
+    #[test]
+    fn too_many_arrows() {
+        assert_eq!(error("en -> de -> fr: x", ""), "Too many arrows, use <source> -> <target>: <text> in en -> de »->« fr: x");
+        assert_eq!(error("en -> de -> fr: x", "it"), "Too many arrows, use <source> -> <target>: <text> in en -> de »->« fr: x");
+    }
+
+    #[test]
+    fn invalid_languages() {
+        assert_eq!(error("de,xx: hi", ""), "Invalid target language code: xx in de,»xx«: hi");
+        assert_eq!(
+            error("de!bogus: hi", ""),
+            "Invalid formality: bogus, use one of more, less, prefer_more, prefer_less, both in de!»bogus«: hi"
+        );
+    }
+
+    #[test]
+    fn default_header_fallback() {
+        // a header that isn't a language is text
+        let query = parsed("note: milk", "en -> de,fr");
+        assert_eq!(query.source, Some(SourceLanguageCode::EN));
+        assert_eq!(languages(&query), vec![TargetLanguageCode::DE, TargetLanguageCode::FR]);
+        assert_eq!(query.text, "note: milk");
+        assert!(query.uses_default);
+
+        // so is one that only comes close to some languages
+        let query = parsed("tip: use the back door", "de");
+        assert_eq!(query.text, "tip: use the back door");
+        assert!(query.uses_default);
+        assert!(matches!(parse("tip: use the back door", ""), Err(DeepLError::InQuery(e, _)) if matches!(*e, DeepLError::MisspelledLanguage(_, _))));
+
+        // a real header still wins over the default
+        assert_eq!(languages(&parsed("fr: x", "de")), vec![TargetLanguageCode::FR]);
+
+        // and a bad default is pointed out
+        assert_eq!(error("hello", "qq"), "Invalid target language code: qq in the default target language »qq«");
+    }
+
+    #[test]
+    fn options() {
+        let query = parsed("de [formal, glossary=product, nosplit] +html: text", "");
+        assert_eq!(query.targets[0].modifier, Some(Modifier::Formality(Formality::More)));
+        assert_eq!(query.options.glossary, Some(GlossaryOption::Named("product".to_owned())));
+        assert_eq!(query.options.split_sentences, Some(SplitSentences::Off));
+        assert_eq!(query.options.tag_handling, Some(TagHandling::Html));
+
+        assert_eq!(parsed("de [context=\"a, b: c\"]: x", "").options.context.as_deref(), Some("a, b: c"));
+        assert_eq!(parsed("de +noglossary: x", "").options.glossary, Some(GlossaryOption::Off));
+    }
+
+    #[test]
+    fn bad_options() {
+        assert_eq!(error("de [formal: x", ""), "The options are missing a closing ] in de »[formal«: x");
+        assert_eq!(error("de [split=7]: x", ""), "Invalid value for the split option: 7 in de [»split=7«]: x");
+        assert!(error("de +htlm: x", "").starts_with("Unknown option: htlm, use "));
+        assert!(error("de +htlm: x", "").ends_with(" in de +»htlm«: x"));
+    }
+
+    #[test]
+    fn context() {
+        let query = parsed("de: bank || context: river side", "");
+        assert_eq!(query.text, "bank");
+        assert_eq!(query.options.context.as_deref(), Some("river side"));
+
+        // the inline context wins over the flag
+        assert_eq!(parsed("de [context=x]: bank ||CONTEXT: \"y, z\"", "").options.context.as_deref(), Some("y, z"));
+
+        // || followed by anything else, or in quotes, is text
+        assert_eq!(parsed("de: a || b", "").text, "a || b");
+        let query = parsed("de: \"a || context: b\"", "");
+        assert_eq!(query.text, "a || context: b");
+        assert_eq!(query.options.context, None);
+
+        let query = parsed("bank || context: money", "de");
+        assert_eq!(query.text, "bank");
+        assert_eq!(query.options.context.as_deref(), Some("money"));
+    }
+
+    #[test]
+    fn empty_modifier_points_at_the_bang() {
+        let expected = "No formality after !, use one of more, less, prefer_more, prefer_less, both in de»!« : hello";
+        assert_eq!(error("de! : hello", ""), expected);
+        assert_eq!(error("de! : hello", "fr"), expected);
+    }
+
+    #[test]
+    fn empty_source_points_at_the_arrow() {
+        let expected = "No source language code before the arrow, leave the arrow out to detect the language in  »->« de: hello";
+        assert_eq!(error(" -> de: hello", ""), expected);
+        assert_eq!(
+            error("\t->de: hello", ""),
+            "No source language code before the arrow, leave the arrow out to detect the language in \t»->«de: hello"
+        );
+    }
+
+    #[test]
+    fn spans_of_blank_parts_are_empty() {
+        assert_eq!(trimmed_span(4, " "), 4..4);
+        assert_eq!(trimmed_span(4, ""), 4..4);
+        assert_eq!(trimmed_span(4, " de "), 5..7);
+        assert_eq!(mark("de,: x", trimmed_span(3, "")), "de,»«: x");
+    }
+}
@@ -0,0 +1,118 @@
+use quick_search_lib::SearchResult;
+
+use crate::{backend::UsageResponse, error::DeepLError, DeepL, DEFAULT_MONTHLY_CHARACTER_BUDGET, DEFAULT_USAGE_WARNING_THRESHOLD};
+
+impl DeepL {
+    // ?usage or deepl: usage shows how much of the billing period's quota is used
+    pub(crate) fn usage_command(&self, query: &str) -> Option<Result<Vec<SearchResult>, DeepLError>> {
+        let is_usage_query = match query.trim().split_once(':') {
+            Some((command, argument)) => command.trim().eq_ignore_ascii_case("deepl") && argument.trim().eq_ignore_ascii_case("usage"),
+            None => query.trim().eq_ignore_ascii_case("?usage"),
+        };
+
+        if !is_usage_query {
+            return None;
+        }
+
+        if self.is_offline() {
+            return Some(Err(DeepLError::Offline));
+        }
+
+        Some(self.backend.usage().map(|usage| self.usage_results(&usage)))
+    }
+
+    fn usage_results(&self, usage: &UsageResponse) -> Vec<SearchResult> {
+        let mut res = vec![];
+
+        let used = percentage(usage.character_count, usage.character_limit);
+        let characters = format!("{} / {} characters", group_digits(usage.character_count), group_digits(usage.character_limit));
+
+        // a threshold of 0 turns the warning off
+        let threshold = self.config_number("Usage warning threshold (%)", DEFAULT_USAGE_WARNING_THRESHOLD);
+        if threshold > 0 && used >= threshold as f64 {
+            let left = usage.character_limit.saturating_sub(usage.character_count);
+            res.push(
+                SearchResult::new("Usage warning")
+                    .set_context(&format!("{:.1}% of the character limit is used, {} characters left", used, group_digits(left)))
+                    .set_extra_info(&characters),
+            );
+        }
+
+        res.push(
+            SearchResult::new(&characters)
+                .set_context(&format!("{:.1}% of the character limit used this billing period", used))
+                .set_extra_info(&characters),
+        );
+
+        // what this plugin counted itself, the api numbers above cover everything using the key
+        let budget = self.config_number("Monthly character budget", DEFAULT_MONTHLY_CHARACTER_BUDGET);
+        let billed = self.budget.lock().map(|budget| budget.used()).unwrap_or(0);
+        let local = if budget > 0 {
+            format!("{} / {} budgeted characters", group_digits(billed), group_digits(budget))
+        } else {
+            format!("{} characters billed", group_digits(billed))
+        };
+        res.push(SearchResult::new(&local).set_context("billed through this plugin this month").set_extra_info(&local));
+
+        if let (Some(count), Some(limit)) = (usage.document_count, usage.document_limit) {
+            let documents = format!("{} / {} documents", group_digits(count), group_digits(limit));
+            res.push(
+                SearchResult::new(&documents)
+                    .set_context(&format!("{:.1}% of the document limit used this billing period", percentage(count, limit)))
+                    .set_extra_info(&documents),
+            );
+        }
+
+        if let (Some(count), Some(limit)) = (usage.team_document_count, usage.team_document_limit) {
+            let documents = format!("{} / {} team documents", group_digits(count), group_digits(limit));
+            res.push(
+                SearchResult::new(&documents)
+                    .set_context(&format!("{:.1}% of the team document limit used this billing period", percentage(count, limit)))
+                    .set_extra_info(&documents),
+            );
+        }
+
+        res
+    }
+}
+
+// no limit at all counts as nothing used, rather than dividing by zero
+fn percentage(count: u64, limit: u64) -> f64 {
+    if limit == 0 {
+        return 0.0;
+    }
+    count as f64 / limit as f64 * 100.0
+}
+
+// 1250000 -> 1,250,000
+fn group_digits(n: u64) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digits_are_grouped_in_threes() {
+        assert_eq!(group_digits(0), "0");
+        assert_eq!(group_digits(999), "999");
+        assert_eq!(group_digits(1000), "1,000");
+        assert_eq!(group_digits(1250000), "1,250,000");
+    }
+
+    #[test]
+    fn percentage_of_no_limit_is_zero() {
+        assert_eq!(percentage(0, 0), 0.0);
+        assert_eq!(percentage(500, 0), 0.0);
+        assert_eq!(percentage(250, 1000), 25.0);
+    }
+}